@@ -0,0 +1,472 @@
+//! The unlock agent: a long-running `lockbox agent` process that keeps the
+//! derived keys of unlocked vaults so that later commands skip both the prompt
+//! and Argon2.
+//!
+//! Clients talk to it over a Unix socket, one JSON message per line. Both
+//! ends check that the other runs as the same user. Keys are held in locked,
+//! wiped memory, one per vault id, and all of them are forgotten once none has
+//! been used for the idle timeout, or on `lockbox lock`.
+//!
+//! The agent only holds what a client hands it after unlocking the vault the
+//! usual way, so it never sees a password or a data key.
+
+use lockbox::{format::SlotKind, keyring::SlotKey, secret::SecretString};
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The environment variable naming the agent's socket.
+pub const SOCKET_ENV: &str = "LOCKBOX_AGENT_SOCK";
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+/// How long either end waits for the other before giving up on a message.
+const IO_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+enum Request {
+    Get {
+        vault_id: String,
+    },
+    Put {
+        vault_id: String,
+        kind: SlotKind,
+        salt_b64: String,
+        key_b64: SecretString,
+    },
+    Lock,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+enum Response {
+    Key {
+        kind: SlotKind,
+        salt_b64: String,
+        key_b64: SecretString,
+    },
+    Missing,
+    Ok,
+    Error {
+        message: String,
+    },
+}
+
+/// Where the agent listens: `$LOCKBOX_AGENT_SOCK`, else a socket in
+/// `$XDG_RUNTIME_DIR`, else one in a private directory under the system
+/// temporary directory.
+pub fn socket_path() -> PathBuf {
+    if let Some(path) = env::var_os(SOCKET_ENV).filter(|p| !p.is_empty()) {
+        return path.into();
+    }
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR").filter(|d| !d.is_empty()) {
+        return Path::new(&dir).join("lockbox-agent.sock");
+    }
+    env::temp_dir()
+        .join(format!("lockbox-{}", current_uid()))
+        .join("agent.sock")
+}
+
+#[cfg(unix)]
+fn current_uid() -> u32 {
+    unsafe { libc::geteuid() }
+}
+
+#[cfg(not(unix))]
+fn current_uid() -> u32 {
+    0
+}
+
+#[cfg(unix)]
+pub use unix::{fetch, lock, serve, store};
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use anyhow::Context;
+    use base64::{Engine, engine::general_purpose};
+    use lockbox::secret::SecretBuf;
+    use orion::aead::SecretKey;
+    use std::{
+        collections::HashMap,
+        fs::{self, DirBuilder},
+        io::{self, Read, Write},
+        os::{
+            fd::AsRawFd,
+            unix::{
+                fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt},
+                net::{UnixListener, UnixStream},
+            },
+        },
+        time::Instant,
+    };
+
+    /// A key held by the agent.
+    struct Held {
+        kind: SlotKind,
+        salt_b64: String,
+        key: SecretBuf,
+    }
+
+    /// The key the agent holds for `vault_id`, if an agent is running and
+    /// has one.
+    pub fn fetch(socket: &Path, vault_id: &str) -> Option<SlotKey> {
+        let request = Request::Get {
+            vault_id: vault_id.to_owned(),
+        };
+        let Ok(Response::Key {
+            kind,
+            salt_b64,
+            key_b64,
+        }) = call(socket, &request)
+        else {
+            return None;
+        };
+        let key = decode_key(&key_b64).ok()?;
+        Some(SlotKey {
+            kind,
+            salt_b64,
+            key,
+        })
+    }
+
+    /// Hands the key of a freshly unlocked vault to the agent, if one is
+    /// running.
+    pub fn store(socket: &Path, vault_id: &str, key: &SlotKey) {
+        let request = Request::Put {
+            vault_id: vault_id.to_owned(),
+            kind: key.kind,
+            salt_b64: key.salt_b64.clone(),
+            key_b64: general_purpose::STANDARD
+                .encode(key.key.unprotected_as_bytes())
+                .into(),
+        };
+        let _ = call(socket, &request);
+    }
+
+    /// Makes the agent forget every key. `Ok(false)` if no agent is running.
+    pub fn lock(socket: &Path) -> anyhow::Result<bool> {
+        match call(socket, &Request::Lock) {
+            Ok(Response::Ok) => Ok(true),
+            Ok(other) => anyhow::bail!("unexpected answer from agent: {other:?}"),
+            Err(e) if not_running(&e) => Ok(false),
+            Err(e) => Err(e).context("unable to reach agent"),
+        }
+    }
+
+    /// Listens on `socket` until the process is killed.
+    pub fn serve(socket: &Path, idle_timeout: Duration) -> anyhow::Result<()> {
+        let listener = bind(socket)?;
+        eprintln!("lockbox agent listening on {}", socket.display());
+        run(&listener, idle_timeout)
+    }
+
+    fn bind(socket: &Path) -> anyhow::Result<UnixListener> {
+        if let Some(dir) = socket.parent().filter(|d| !d.as_os_str().is_empty()) {
+            if !dir.exists() {
+                DirBuilder::new()
+                    .recursive(true)
+                    .mode(0o700)
+                    .create(dir)
+                    .with_context(|| format!("unable to create {}", dir.display()))?;
+            }
+            let meta = fs::metadata(dir)?;
+            if meta.uid() != current_uid() || meta.mode() & 0o022 != 0 {
+                anyhow::bail!(
+                    "{} must be owned by you and not writable by others",
+                    dir.display()
+                );
+            }
+        }
+        if let Ok(meta) = fs::symlink_metadata(socket) {
+            if UnixStream::connect(socket).is_ok() {
+                anyhow::bail!("an agent is already running at {}", socket.display());
+            }
+            if !meta.file_type().is_socket() {
+                anyhow::bail!("{} exists and is not a socket", socket.display());
+            }
+            // Left behind by an agent that was killed.
+            fs::remove_file(socket)?;
+        }
+        let listener = UnixListener::bind(socket)
+            .with_context(|| format!("unable to listen on {}", socket.display()))?;
+        fs::set_permissions(socket, fs::Permissions::from_mode(0o600))?;
+        Ok(listener)
+    }
+
+    fn run(listener: &UnixListener, idle_timeout: Duration) -> anyhow::Result<()> {
+        let mut keys = HashMap::<String, Held>::new();
+        let mut last_used = Instant::now();
+        loop {
+            let wait = (!keys.is_empty()).then(|| idle_timeout.saturating_sub(last_used.elapsed()));
+            if !wait_for_client(listener, wait)? {
+                keys.clear();
+                eprintln!("lockbox agent locked after being idle");
+                continue;
+            }
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    eprintln!("lockbox agent: accept failed: {e}");
+                    continue;
+                }
+            };
+            match peer_uid(&stream) {
+                Ok(uid) if uid == current_uid() => {}
+                Ok(uid) => {
+                    eprintln!("lockbox agent: refused a client running as uid {uid}");
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("lockbox agent: unable to identify a client: {e}");
+                    continue;
+                }
+            }
+            match handle(&stream, &mut keys) {
+                Ok(true) => last_used = Instant::now(),
+                Ok(false) => {}
+                Err(e) => eprintln!("lockbox agent: {e:#}"),
+            }
+        }
+    }
+
+    /// Answers one request. `Ok(true)` if it used or stored a key.
+    fn handle(mut stream: &UnixStream, keys: &mut HashMap<String, Held>) -> anyhow::Result<bool> {
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        let request = match receive::<Request>(&mut stream) {
+            Ok(request) => request,
+            Err(e) => {
+                let message = format!("{e:#}");
+                send(&mut stream, &Response::Error { message })?;
+                return Ok(false);
+            }
+        };
+        let (response, used) = match request {
+            Request::Get { vault_id } => match keys.get(&vault_id) {
+                Some(held) => (
+                    Response::Key {
+                        kind: held.kind,
+                        salt_b64: held.salt_b64.clone(),
+                        key_b64: general_purpose::STANDARD.encode(&*held.key).into(),
+                    },
+                    true,
+                ),
+                None => (Response::Missing, false),
+            },
+            Request::Put {
+                vault_id,
+                kind,
+                salt_b64,
+                key_b64,
+            } => {
+                let key = decode_key(&key_b64)?;
+                let mut held = SecretBuf::zeroed(key.len());
+                held.as_mut_slice()
+                    .copy_from_slice(key.unprotected_as_bytes());
+                keys.insert(
+                    vault_id,
+                    Held {
+                        kind,
+                        salt_b64,
+                        key: held,
+                    },
+                );
+                (Response::Ok, true)
+            }
+            Request::Lock => {
+                keys.clear();
+                (Response::Ok, false)
+            }
+        };
+        send(&mut stream, &response)?;
+        Ok(used)
+    }
+
+    fn call(socket: &Path, request: &Request) -> anyhow::Result<Response> {
+        let stream = UnixStream::connect(socket)?;
+        let uid = peer_uid(&stream)?;
+        if uid != current_uid() {
+            anyhow::bail!("the agent socket belongs to uid {uid}");
+        }
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        send(&mut &stream, request)?;
+        match receive(&mut &stream)? {
+            Response::Error { message } => anyhow::bail!("agent: {message}"),
+            response => Ok(response),
+        }
+    }
+
+    fn not_running(e: &anyhow::Error) -> bool {
+        e.downcast_ref::<io::Error>().is_some_and(|e| {
+            matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            )
+        })
+    }
+
+    fn send<T: Serialize>(stream: &mut &UnixStream, message: &T) -> anyhow::Result<()> {
+        let mut line = SecretBuf::default();
+        serde_json::to_writer(&mut line, message)?;
+        line.extend_from_slice(b"\n");
+        stream.write_all(&line)?;
+        Ok(())
+    }
+
+    /// Reads one line a byte at a time, so that nothing past it is taken from
+    /// the socket and nothing of it is left in an unwiped buffer.
+    fn receive<T: for<'de> Deserialize<'de>>(stream: &mut &UnixStream) -> anyhow::Result<T> {
+        let mut line = SecretBuf::default();
+        let mut byte = [0u8; 1];
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                anyhow::bail!("connection closed mid-message");
+            }
+            if byte[0] == b'\n' {
+                break;
+            }
+            if line.len() == MAX_MESSAGE_LEN {
+                anyhow::bail!("message too long");
+            }
+            line.extend_from_slice(&byte);
+        }
+        Ok(serde_json::from_slice(&line)?)
+    }
+
+    fn decode_key(key_b64: &str) -> anyhow::Result<SecretKey> {
+        let mut bytes = SecretBuf::zeroed(key_b64.len());
+        let len = general_purpose::STANDARD
+            .decode_slice(key_b64, bytes.as_mut_slice())
+            .context("malformed key")?;
+        SecretKey::from_slice(&bytes[..len]).context("malformed key")
+    }
+
+    /// Waits for a connection for at most `timeout`, or forever if `None`.
+    /// `Ok(false)` if the timeout passed.
+    fn wait_for_client(listener: &UnixListener, timeout: Option<Duration>) -> anyhow::Result<bool> {
+        let millis = timeout.map_or(-1, |t| i32::try_from(t.as_millis()).unwrap_or(i32::MAX));
+        let mut fd = libc::pollfd {
+            fd: listener.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        loop {
+            match unsafe { libc::poll(&mut fd, 1, millis) } {
+                -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+                -1 => return Err(io::Error::last_os_error().into()),
+                0 => return Ok(false),
+                _ => return Ok(true),
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+        let mut cred = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let rc = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                (&mut cred as *mut libc::ucred).cast(),
+                &mut len,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(cred.uid)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+        let (mut uid, mut gid) = (0, 0);
+        if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(uid)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::thread;
+
+        fn start(idle_timeout: Duration) -> (tempfile::TempDir, PathBuf) {
+            let dir = tempfile::tempdir().unwrap();
+            let socket = dir.path().join("agent.sock");
+            let listener = bind(&socket).unwrap();
+            thread::spawn(move || run(&listener, idle_timeout));
+            (dir, socket)
+        }
+
+        fn slot_key(byte: u8) -> SlotKey {
+            SlotKey {
+                kind: SlotKind::Password,
+                salt_b64: "c2FsdA==".into(),
+                key: SecretKey::from_slice(&[byte; 32]).unwrap(),
+            }
+        }
+
+        #[test]
+        fn holds_keys_until_locked() {
+            let (_dir, socket) = start(Duration::from_secs(60));
+
+            assert!(fetch(&socket, "vault").is_none());
+            store(&socket, "vault", &slot_key(7));
+            let held = fetch(&socket, "vault").unwrap();
+            assert_eq!(held.key.unprotected_as_bytes(), [7; 32]);
+            assert_eq!(held.salt_b64, "c2FsdA==");
+            assert!(fetch(&socket, "other").is_none());
+
+            assert!(lock(&socket).unwrap());
+            assert!(fetch(&socket, "vault").is_none());
+        }
+
+        #[test]
+        fn forgets_keys_when_idle() {
+            let (_dir, socket) = start(Duration::from_millis(100));
+            store(&socket, "vault", &slot_key(1));
+
+            thread::sleep(Duration::from_millis(300));
+
+            assert!(fetch(&socket, "vault").is_none());
+        }
+
+        #[test]
+        fn refuses_to_replace_a_running_agent() {
+            let (_dir, socket) = start(Duration::from_secs(60));
+
+            assert!(bind(&socket).is_err());
+            assert!(!lock(&socket.with_file_name("absent.sock")).unwrap());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn fetch(_socket: &Path, _vault_id: &str) -> Option<SlotKey> {
+    None
+}
+
+#[cfg(not(unix))]
+pub fn store(_socket: &Path, _vault_id: &str, _key: &SlotKey) {}
+
+#[cfg(not(unix))]
+pub fn lock(_socket: &Path) -> anyhow::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(not(unix))]
+pub fn serve(_socket: &Path, _idle_timeout: Duration) -> anyhow::Result<()> {
+    anyhow::bail!("the agent needs Unix domain sockets")
+}
@@ -0,0 +1,297 @@
+//! Crash-safe replacement of a file's contents.
+//!
+//! New contents go to a uniquely named temp file in the same directory,
+//! created with `0600` permissions and fsynced before it is renamed over the
+//! target; the directory is fsynced afterwards so the rename itself survives a
+//! crash. Temp files are named `.<target>.<pid>.<random>.tmp`, which lets a
+//! later save recognise and remove ones whose writer has died.
+
+use std::{
+    ffi::OsStr,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// A fully written and synced temp file waiting to replace its target. It is
+/// removed again if dropped before [`TempFile::commit`].
+pub struct TempFile {
+    path: PathBuf,
+    target: PathBuf,
+    committed: bool,
+}
+
+impl TempFile {
+    pub fn create(target: &Path, bytes: &[u8]) -> io::Result<Self> {
+        let name = target
+            .file_name()
+            .and_then(OsStr::to_str)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid vault path"))?;
+        let mut random = [0u8; 4];
+        orion::util::secure_rand_bytes(&mut random).map_err(io::Error::other)?;
+        let temp_name = format!(
+            ".{name}.{}.{}.tmp",
+            std::process::id(),
+            crate::crypto::hex(&random)
+        );
+
+        let path = parent_dir(target).join(temp_name);
+
+        fail_point("create")?;
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut f = options.open(&path)?;
+
+        let temp = TempFile {
+            path,
+            target: target.to_path_buf(),
+            committed: false,
+        };
+        write_all(&mut f, bytes)?;
+        fail_point("sync")?;
+        f.sync_all()?;
+        Ok(temp)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Renames the temp file over its target and makes the rename durable.
+    pub fn commit(mut self) -> io::Result<()> {
+        fail_point("rename")?;
+        fs::rename(&self.path, &self.target)?;
+        self.committed = true;
+        fail_point("sync_dir")?;
+        sync_dir(parent_dir(&self.target))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Atomically replaces the contents of `target` with `bytes`.
+pub fn write(target: &Path, bytes: &[u8]) -> io::Result<()> {
+    TempFile::create(target, bytes)?.commit()
+}
+
+/// Removes temp files left next to `target` by saves whose process is gone,
+/// returning the paths removed.
+pub fn remove_stale_temp_files(target: &Path) -> io::Result<Vec<PathBuf>> {
+    let Some(name) = target.file_name().and_then(OsStr::to_str) else {
+        return Ok(vec![]);
+    };
+    let prefix = format!(".{name}.");
+    let mut removed = vec![];
+
+    // Earlier releases always saved through the same fixed temp name.
+    let legacy = target.with_extension("json.tmp");
+    if legacy.is_file() {
+        fs::remove_file(&legacy)?;
+        removed.push(legacy);
+    }
+
+    for entry in fs::read_dir(parent_dir(target))? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(rest) = file_name
+            .to_str()
+            .and_then(|n| n.strip_prefix(&prefix))
+            .and_then(|n| n.strip_suffix(".tmp"))
+        else {
+            continue;
+        };
+        let Some(pid) = rest.split('.').next().and_then(|p| p.parse::<u32>().ok()) else {
+            continue;
+        };
+        if !process_alive(pid) {
+            fs::remove_file(entry.path())?;
+            removed.push(entry.path());
+        }
+    }
+    removed.sort();
+    Ok(removed)
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    }
+}
+
+fn write_all(f: &mut File, bytes: &[u8]) -> io::Result<()> {
+    if fail_point("write").is_err() {
+        // Simulate a crash halfway through the write.
+        f.write_all(&bytes[..bytes.len() / 2])?;
+        return fail_point("write");
+    }
+    f.write_all(bytes)
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return true;
+    }
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks whether the process exists. EPERM means it does
+    // but belongs to someone else.
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_alive(pid: u32) -> bool {
+    pid == std::process::id()
+}
+
+#[cfg(not(test))]
+fn fail_point(_step: &str) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+thread_local! {
+    static FAIL_AT: std::cell::Cell<Option<&'static str>> = const { std::cell::Cell::new(None) };
+}
+
+#[cfg(test)]
+fn fail_point(step: &str) -> io::Result<()> {
+    match FAIL_AT.with(|f| f.get()) {
+        Some(at) if at == step => Err(io::Error::other(format!("injected failure at {step}"))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_files(dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.to_string_lossy().ends_with(".tmp"))
+            .collect()
+    }
+
+    fn write_failing_at(target: &Path, bytes: &[u8], step: &'static str) -> io::Result<()> {
+        FAIL_AT.with(|f| f.set(Some(step)));
+        let result = write(target, bytes);
+        FAIL_AT.with(|f| f.set(None));
+        result
+    }
+
+    #[test]
+    fn write_replaces_contents_without_leaving_temp_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("db.json");
+        fs::write(&target, b"old").unwrap();
+
+        write(&target, b"new contents").unwrap();
+
+        assert_eq!(fs::read(&target).unwrap(), b"new contents");
+        assert!(temp_files(dir.path()).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn written_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("db.json");
+        fs::write(&target, b"old").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write(&target, b"new").unwrap();
+
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn failure_before_rename_keeps_original() {
+        for step in ["create", "write", "sync", "rename"] {
+            let dir = tempfile::tempdir().unwrap();
+            let target = dir.path().join("db.json");
+            fs::write(&target, b"original").unwrap();
+
+            let err = write_failing_at(&target, b"replacement", step).unwrap_err();
+
+            assert!(err.to_string().contains(step), "{step}: {err}");
+            assert_eq!(fs::read(&target).unwrap(), b"original", "{step}");
+            assert!(temp_files(dir.path()).is_empty(), "{step}");
+        }
+    }
+
+    #[test]
+    fn failure_after_rename_keeps_new_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("db.json");
+        fs::write(&target, b"original").unwrap();
+
+        assert!(write_failing_at(&target, b"replacement", "sync_dir").is_err());
+
+        assert_eq!(fs::read(&target).unwrap(), b"replacement");
+        assert!(temp_files(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn stale_temp_files_from_dead_processes_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("db.json");
+        let dead = dir
+            .path()
+            .join(format!(".db.json.{}.deadbeef.tmp", i32::MAX));
+        let live = dir
+            .path()
+            .join(format!(".db.json.{}.cafef00d.tmp", std::process::id()));
+        let legacy = dir.path().join("db.json.tmp");
+        let unrelated = dir.path().join(".other.json.1.00000000.tmp");
+        for p in [&dead, &live, &legacy, &unrelated] {
+            fs::write(p, b"partial").unwrap();
+        }
+
+        let removed = remove_stale_temp_files(&target).unwrap();
+
+        let mut expected = vec![dead.clone(), legacy.clone()];
+        expected.sort();
+        assert_eq!(removed, expected);
+        assert!(!dead.exists() && !legacy.exists());
+        assert!(live.exists() && unrelated.exists());
+    }
+
+    #[test]
+    fn uncommitted_temp_file_is_removed_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("db.json");
+
+        let temp = TempFile::create(&target, b"pending").unwrap();
+        assert_eq!(fs::read(temp.path()).unwrap(), b"pending");
+        drop(temp);
+
+        assert!(!target.exists());
+        assert!(temp_files(dir.path()).is_empty());
+    }
+}
@@ -0,0 +1,283 @@
+//! Rotating copies of the encrypted vault file.
+//!
+//! Before every save the file about to be replaced is copied, still
+//! encrypted, into `<vault>.backups/<millis>.json`. Older copies are then
+//! pruned according to a [`BackupPolicy`]: the most recent `keep_last`, plus
+//! the newest copy from each of the last `daily` days and `weekly` weeks that
+//! have one.
+
+use crate::atomic;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::BTreeSet,
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+const DAY_MS: u64 = 86_400_000;
+const WEEK_MS: u64 = 7 * DAY_MS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupPolicy {
+    pub keep_last: usize,
+    pub daily: usize,
+    pub weekly: usize,
+}
+
+impl Default for BackupPolicy {
+    fn default() -> Self {
+        Self {
+            keep_last: 10,
+            daily: 7,
+            weekly: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    /// Milliseconds since the epoch when the copy was taken; also its id.
+    pub taken_ms: u64,
+    pub path: PathBuf,
+}
+
+impl Backup {
+    pub fn id(&self) -> String {
+        self.taken_ms.to_string()
+    }
+}
+
+pub fn dir(vault: &Path) -> PathBuf {
+    let mut name = vault
+        .file_name()
+        .map(OsString::from)
+        .unwrap_or_else(|| OsString::from("vault"));
+    name.push(".backups");
+    vault.with_file_name(name)
+}
+
+/// All backups of `vault`, newest first.
+pub fn list(vault: &Path) -> io::Result<Vec<Backup>> {
+    let dir = dir(vault);
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut backups = vec![];
+    for entry in fs::read_dir(&dir)? {
+        let entry = entry?;
+        let taken_ms = entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.strip_suffix(".json"))
+            .and_then(|n| n.parse().ok());
+        if let Some(taken_ms) = taken_ms {
+            backups.push(Backup {
+                taken_ms,
+                path: entry.path(),
+            });
+        }
+    }
+    backups.sort_by_key(|b| Reverse(b.taken_ms));
+    Ok(backups)
+}
+
+pub fn find(vault: &Path, id: &str) -> io::Result<Backup> {
+    list(vault)?
+        .into_iter()
+        .find(|b| b.id() == id)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no backup with id {id}")))
+}
+
+/// Copies the current contents of `vault`, if it exists, into the backup
+/// directory and prunes old copies. Returns the new backup.
+pub fn snapshot(vault: &Path, policy: &BackupPolicy, now_ms: u64) -> io::Result<Option<Backup>> {
+    let bytes = match fs::read(vault) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let dir = dir(vault);
+    create_private_dir(&dir)?;
+
+    // Ids are timestamps; step past any taken within the same millisecond.
+    let taken = list(vault)?
+        .iter()
+        .map(|b| b.taken_ms)
+        .collect::<BTreeSet<_>>();
+    let mut taken_ms = now_ms;
+    while taken.contains(&taken_ms) {
+        taken_ms += 1;
+    }
+    let backup = Backup {
+        taken_ms,
+        path: dir.join(format!("{taken_ms}.json")),
+    };
+    atomic::write(&backup.path, &bytes)?;
+    prune(vault, policy)?;
+    Ok(Some(backup))
+}
+
+/// Atomically replaces `vault` with the backup `id`. The file being replaced
+/// is backed up first, so a restore can itself be undone.
+pub fn restore(vault: &Path, id: &str, policy: &BackupPolicy, now_ms: u64) -> io::Result<Backup> {
+    let backup = find(vault, id)?;
+    let bytes = fs::read(&backup.path)?;
+    snapshot(vault, policy, now_ms)?;
+    atomic::write(vault, &bytes)?;
+    Ok(backup)
+}
+
+/// Deletes backups the policy no longer asks for, returning them.
+pub fn prune(vault: &Path, policy: &BackupPolicy) -> io::Result<Vec<Backup>> {
+    let backups = list(vault)?;
+    let times = backups.iter().map(|b| b.taken_ms).collect::<Vec<_>>();
+    let keep = retained(&times, policy);
+    let mut removed = vec![];
+    for backup in backups {
+        if !keep.contains(&backup.taken_ms) {
+            fs::remove_file(&backup.path)?;
+            removed.push(backup);
+        }
+    }
+    Ok(removed)
+}
+
+/// Which of `times` (any order) the policy keeps.
+fn retained(times: &[u64], policy: &BackupPolicy) -> BTreeSet<u64> {
+    let mut newest_first = times.to_vec();
+    newest_first.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut keep = newest_first
+        .iter()
+        .take(policy.keep_last)
+        .copied()
+        .collect::<BTreeSet<_>>();
+    for (bucket_ms, count) in [(DAY_MS, policy.daily), (WEEK_MS, policy.weekly)] {
+        let mut buckets = BTreeSet::new();
+        for &t in &newest_first {
+            if buckets.len() == count {
+                break;
+            }
+            if buckets.insert(t / bucket_ms) {
+                keep.insert(t);
+            }
+        }
+    }
+    keep
+}
+
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_MS: u64 = 3_600_000;
+
+    #[test]
+    fn keeps_last_n_plus_daily_and_weekly() {
+        let policy = BackupPolicy {
+            keep_last: 2,
+            daily: 2,
+            weekly: 2,
+        };
+        // Three saves today, two yesterday, one a week ago, one two weeks ago.
+        let today = 30 * DAY_MS;
+        let times = [
+            today + 3 * HOUR_MS,
+            today + 2 * HOUR_MS,
+            today + HOUR_MS,
+            today - HOUR_MS,
+            today - 2 * HOUR_MS,
+            today - WEEK_MS,
+            today - 2 * WEEK_MS,
+        ];
+
+        let keep = retained(&times, &policy);
+
+        let expected = [
+            today + 3 * HOUR_MS, // last 2, newest of today, newest of this week
+            today + 2 * HOUR_MS, // last 2
+            today - HOUR_MS,     // newest of yesterday
+            today - WEEK_MS,     // newest of last week
+        ];
+        assert_eq!(keep, expected.into_iter().collect());
+    }
+
+    #[test]
+    fn snapshot_copies_and_prunes() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = dir.path().join("db.json");
+        let policy = BackupPolicy {
+            keep_last: 2,
+            daily: 0,
+            weekly: 0,
+        };
+
+        for (i, contents) in ["one", "two", "three"].iter().enumerate() {
+            fs::write(&vault, contents).unwrap();
+            snapshot(&vault, &policy, 1_000 + i as u64).unwrap();
+        }
+
+        let backups = list(&vault).unwrap();
+        let contents = backups
+            .iter()
+            .map(|b| fs::read_to_string(&b.path).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(contents, ["three", "two"]);
+    }
+
+    #[test]
+    fn snapshot_ids_are_unique_within_a_millisecond() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = dir.path().join("db.json");
+        fs::write(&vault, "v").unwrap();
+
+        let a = snapshot(&vault, &BackupPolicy::default(), 5)
+            .unwrap()
+            .unwrap();
+        let b = snapshot(&vault, &BackupPolicy::default(), 5)
+            .unwrap()
+            .unwrap();
+
+        assert_ne!(a.id(), b.id());
+    }
+
+    #[test]
+    fn restore_replaces_vault_and_backs_up_current() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = dir.path().join("db.json");
+        let policy = BackupPolicy::default();
+        fs::write(&vault, "old").unwrap();
+        let old = snapshot(&vault, &policy, 1_000).unwrap().unwrap();
+        fs::write(&vault, "current").unwrap();
+
+        restore(&vault, &old.id(), &policy, 2_000).unwrap();
+
+        assert_eq!(fs::read_to_string(&vault).unwrap(), "old");
+        let undo = find(&vault, "2000").unwrap();
+        assert_eq!(fs::read_to_string(undo.path).unwrap(), "current");
+    }
+
+    #[test]
+    fn restore_of_unknown_id_leaves_vault_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = dir.path().join("db.json");
+        fs::write(&vault, "current").unwrap();
+
+        let err = restore(&vault, "42", &BackupPolicy::default(), 1_000).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(fs::read_to_string(&vault).unwrap(), "current");
+        assert!(list(&vault).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,107 @@
+//! XChaCha20-Poly1305 sealing with associated data, plus the small helpers
+//! built on top of it.
+
+use crate::secret::SecretBuf;
+use orion::{
+    aead::SecretKey,
+    hazardous::{
+        aead::xchacha20poly1305::{self, Nonce},
+        mac::poly1305::POLY1305_OUTSIZE,
+        stream::xchacha20::XCHACHA_NONCESIZE,
+    },
+    util::secure_rand_bytes,
+};
+
+const KEY_CHECK_CONTEXT: &[u8] = b"lockbox key check v1";
+
+fn chacha_key(
+    key: &SecretKey,
+) -> Result<xchacha20poly1305::SecretKey, orion::errors::UnknownCryptoError> {
+    xchacha20poly1305::SecretKey::from_slice(key.unprotected_as_bytes())
+}
+
+/// Encrypts `plaintext` under a fresh random nonce, binding `ad` to the
+/// result. The output is laid out as `nonce || ciphertext || tag`, the same
+/// layout `orion::aead::seal` produces, so a `None` here matches that API.
+pub fn seal(key: &SecretKey, plaintext: &[u8], ad: Option<&[u8]>) -> anyhow::Result<Vec<u8>> {
+    let nonce = Nonce::generate();
+    let mut out = vec![0u8; XCHACHA_NONCESIZE + plaintext.len() + POLY1305_OUTSIZE];
+    out[..XCHACHA_NONCESIZE].copy_from_slice(nonce.as_ref());
+    xchacha20poly1305::seal(
+        &chacha_key(key)?,
+        &nonce,
+        plaintext,
+        ad,
+        &mut out[XCHACHA_NONCESIZE..],
+    )?;
+    Ok(out)
+}
+
+/// Reverses [`seal`]. Fails if the key is wrong or if `blob` or `ad` were
+/// changed in any way.
+pub fn open(
+    key: &SecretKey,
+    blob: &[u8],
+    ad: Option<&[u8]>,
+) -> Result<SecretBuf, orion::errors::UnknownCryptoError> {
+    if blob.len() < XCHACHA_NONCESIZE + POLY1305_OUTSIZE {
+        return Err(orion::errors::UnknownCryptoError);
+    }
+    let (nonce, sealed) = blob.split_at(XCHACHA_NONCESIZE);
+    let mut out = SecretBuf::zeroed(sealed.len() - POLY1305_OUTSIZE);
+    xchacha20poly1305::open(
+        &chacha_key(key)?,
+        &Nonce::from_slice(nonce)?,
+        sealed,
+        ad,
+        out.as_mut_slice(),
+    )?;
+    Ok(out)
+}
+
+/// A keyed BLAKE2b tag over a fixed context string, stored with a key slot so
+/// a wrong key can be told apart from a modified file.
+pub fn key_check(key: &SecretKey) -> anyhow::Result<Vec<u8>> {
+    let mac_key = orion::auth::SecretKey::from_slice(key.unprotected_as_bytes())?;
+    Ok(orion::auth::authenticate(&mac_key, KEY_CHECK_CONTEXT)?
+        .unprotected_as_bytes()
+        .to_vec())
+}
+
+/// Checks a tag made by [`key_check`]; version 2 files stored one next to the
+/// ciphertext.
+pub fn verify_key_check(key: &SecretKey, expected: &[u8]) -> bool {
+    let Ok(mac_key) = orion::auth::SecretKey::from_slice(key.unprotected_as_bytes()) else {
+        return false;
+    };
+    let Ok(tag) = orion::auth::Tag::from_slice(expected) else {
+        return false;
+    };
+    orion::auth::authenticate_verify(&tag, &mac_key, KEY_CHECK_CONTEXT).is_ok()
+}
+
+/// A random identifier, hex encoded.
+pub fn random_id() -> anyhow::Result<String> {
+    random_hex(16)
+}
+
+/// `len` random bytes, hex encoded.
+pub fn random_hex(len: usize) -> anyhow::Result<String> {
+    let mut bytes = vec![0u8; len];
+    secure_rand_bytes(&mut bytes)?;
+    Ok(hex(&bytes))
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
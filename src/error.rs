@@ -0,0 +1,242 @@
+use crate::lock::LockError;
+use std::{fmt, path::PathBuf};
+
+/// Reasons a vault file could not be opened. Any of these aborts the command
+/// before it gets a chance to save over the file.
+#[derive(Debug)]
+pub enum LoadError {
+    NotFound(PathBuf),
+    Io(std::io::Error),
+    Corrupt(String),
+    UnsupportedKdf {
+        iterations: u32,
+        memory_kib: u32,
+    },
+    UnsupportedFormat(String),
+    WrongPassword,
+    /// A keyfile was involved, so it may be the keyfile that is wrong.
+    WrongKeyfile,
+    /// The secret rebuilt from recovery shares opens no key slot.
+    WrongRecoveryKey,
+    /// Every key slot needs a keyfile and none was given.
+    KeyfileRequired,
+    /// An unencrypted store from an early release; see `migrate-plaintext`.
+    Plaintext,
+    /// The key was right but the file did not authenticate.
+    Integrity(String),
+}
+
+impl LoadError {
+    /// Process exit code for this failure, following the BSD `sysexits.h` values.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            LoadError::NotFound(_) => 66,
+            LoadError::Io(_) => 74,
+            LoadError::Corrupt(_) | LoadError::Integrity(_) | LoadError::Plaintext => 65,
+            LoadError::UnsupportedKdf { .. } | LoadError::UnsupportedFormat(_) => 78,
+            LoadError::WrongPassword
+            | LoadError::WrongKeyfile
+            | LoadError::WrongRecoveryKey
+            | LoadError::KeyfileRequired => 77,
+        }
+    }
+
+    /// A short, stable name for this failure, for programs that read it.
+    pub fn code(&self) -> &'static str {
+        match self {
+            LoadError::NotFound(_) => "not-found",
+            LoadError::Io(_) => "io",
+            LoadError::Corrupt(_) => "corrupt",
+            LoadError::UnsupportedKdf { .. } => "unsupported-kdf",
+            LoadError::UnsupportedFormat(_) => "unsupported-format",
+            LoadError::WrongPassword => "wrong-password",
+            LoadError::WrongKeyfile => "wrong-keyfile",
+            LoadError::WrongRecoveryKey => "wrong-recovery-key",
+            LoadError::KeyfileRequired => "keyfile-required",
+            LoadError::Plaintext => "plaintext",
+            LoadError::Integrity(_) => "integrity",
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::NotFound(path) => write!(
+                f,
+                "no vault at {}; run `lockbox init` to create one",
+                path.display()
+            ),
+            LoadError::Io(e) => write!(f, "unable to read vault: {e}"),
+            LoadError::Corrupt(msg) => write!(f, "vault file is corrupt: {msg}"),
+            LoadError::UnsupportedKdf {
+                iterations,
+                memory_kib,
+            } => write!(
+                f,
+                "unsupported KDF parameters (iterations={iterations}, memory_kib={memory_kib})"
+            ),
+            LoadError::UnsupportedFormat(msg) => write!(f, "unsupported vault format: {msg}"),
+            LoadError::WrongPassword => write!(f, "wrong master password"),
+            LoadError::WrongKeyfile => write!(f, "wrong keyfile or master password"),
+            LoadError::WrongRecoveryKey => write!(f, "recovery key does not unlock this vault"),
+            LoadError::KeyfileRequired => {
+                write!(f, "this vault needs a keyfile; pass --keyfile <path>")
+            }
+            LoadError::Plaintext => write!(
+                f,
+                "vault file is not encrypted; run `lockbox migrate-plaintext` to encrypt it"
+            ),
+            LoadError::Integrity(msg) => write!(f, "vault failed its integrity check: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Everything a [`VaultHandle`](crate::VaultHandle) operation can fail with.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The vault file could not be opened.
+    Load(LoadError),
+    /// Another process holds the vault.
+    Lock(LockError),
+    /// `create` found a file already at the path.
+    AlreadyExists(PathBuf),
+    /// The vault has not been unlocked, or has been locked again.
+    Locked,
+    /// The handle was opened with a shared lock, which does not allow saving.
+    ReadOnly,
+    NoSuchEntry(usize),
+    /// No entry has this id or name.
+    NoMatch(String),
+    /// More than one entry has this name.
+    Ambiguous {
+        query: String,
+        ids: Vec<usize>,
+    },
+    /// The entry has no earlier value with this number, counting from 1.
+    NoSuchVersion {
+        id: usize,
+        number: usize,
+    },
+    /// An entry's values are not valid for its type.
+    Invalid(String),
+    /// Writing the vault failed.
+    Io(std::io::Error),
+    /// Anything else, such as a failure in key derivation or encryption.
+    Other(anyhow::Error),
+}
+
+impl Error {
+    /// Process exit code for this failure, following the BSD `sysexits.h` values.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Load(e) => e.exit_code(),
+            Error::Lock(e) => e.exit_code(),
+            Error::AlreadyExists(_) => 73,
+            Error::Invalid(_) => 65,
+            Error::Io(_) => 74,
+            Error::Locked
+            | Error::ReadOnly
+            | Error::NoSuchEntry(_)
+            | Error::NoMatch(_)
+            | Error::Ambiguous { .. }
+            | Error::NoSuchVersion { .. }
+            | Error::Other(_) => 1,
+        }
+    }
+
+    /// A short, stable name for this failure, for programs that read it.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Load(e) => e.code(),
+            Error::Lock(e) => e.code(),
+            Error::AlreadyExists(_) => "already-exists",
+            Error::Locked => "locked",
+            Error::ReadOnly => "read-only",
+            Error::NoSuchEntry(_) | Error::NoMatch(_) => "no-such-entry",
+            Error::Ambiguous { .. } => "ambiguous",
+            Error::NoSuchVersion { .. } => "no-such-version",
+            Error::Invalid(_) => "invalid-entry",
+            Error::Io(_) => "io",
+            Error::Other(_) => "error",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Load(e) => e.fmt(f),
+            Error::Lock(e) => e.fmt(f),
+            Error::AlreadyExists(path) => write!(
+                f,
+                "{} already exists; refusing to overwrite it",
+                path.display()
+            ),
+            Error::Locked => write!(f, "vault is locked"),
+            Error::ReadOnly => write!(f, "vault was opened read-only"),
+            Error::NoSuchEntry(id) => write!(f, "no entry with id {id}"),
+            Error::NoMatch(query) => write!(f, "no entry has the id or name {query:?}"),
+            Error::Ambiguous { query, ids } => {
+                let ids = ids.iter().map(usize::to_string).collect::<Vec<_>>();
+                write!(
+                    f,
+                    "{} entries are named {query:?} (ids {}); give an id",
+                    ids.len(),
+                    ids.join(", ")
+                )
+            }
+            Error::NoSuchVersion { id, number } => {
+                write!(f, "entry {id} has no earlier value number {number}")
+            }
+            Error::Invalid(msg) => write!(f, "invalid entry: {msg}"),
+            Error::Io(e) => write!(f, "unable to write vault: {e}"),
+            Error::Other(e) => write!(f, "{e:#}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Load(e) => e.source(),
+            Error::Lock(e) => e.source(),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<LoadError> for Error {
+    fn from(e: LoadError) -> Self {
+        Error::Load(e)
+    }
+}
+
+impl From<LockError> for Error {
+    fn from(e: LockError) -> Self {
+        Error::Lock(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        Error::Other(e)
+    }
+}
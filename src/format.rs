@@ -0,0 +1,284 @@
+//! On-disk layout of a vault file and the migrations between its versions.
+//!
+//! Every file written today starts with a [`Header`] naming its format version
+//! and cipher, and listing the [`KeySlot`]s that can unlock it. Files from
+//! older releases are upgraded in memory by running them through
+//! [`MIGRATIONS`] one version at a time, so the rest of lockbox only ever sees
+//! the current [`EncryptedFile`] layout. The upgraded layout is written back
+//! on the next save.
+//!
+//! From version 2 on, the header is sealed as associated data of the
+//! ciphertext. The bytes authenticated are the header object exactly as it
+//! appears in the file, serialized with sorted keys, so they survive any later
+//! migration of the in-memory layout. From version 3 the key slots are left out
+//! of that: each slot is authenticated by its own wrapping instead, so slots
+//! can be added or revoked without touching the data.
+
+use crate::{error::LoadError, kdf::KdfParams};
+use base64::{Engine, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::fmt;
+
+/// Version written by this build.
+pub const FORMAT_VERSION: u32 = 3;
+
+pub const CIPHER_XCHACHA20_POLY1305: &str = "xchacha20-poly1305";
+pub const KDF_ARGON2I: &str = "argon2i";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Header {
+    pub format_version: u32,
+    pub vault_id: String,
+    pub cipher: String,
+    pub key_slots: Vec<KeySlot>,
+}
+
+impl Header {
+    /// Canonical bytes of this header as they are bound to the ciphertext.
+    pub fn associated_data(&self) -> Vec<u8> {
+        let value = serde_json::to_value(self).expect("header serializes");
+        stored_associated_data(FORMAT_VERSION, &value).expect("current version has associated data")
+    }
+}
+
+/// One way of unlocking the vault: the data key sealed under a key derived
+/// from some secret.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeySlot {
+    pub id: String,
+    pub kind: SlotKind,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub label: String,
+    pub kdf: String,
+    pub kdf_iterations: u32,
+    pub kdf_memory_kib: u32,
+    pub salt_b64: String,
+    /// The data key sealed under this slot's key. `None` only for the slot of
+    /// a file migrated from version 2, whose derived key *is* the data key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapped_key_b64: Option<String>,
+    /// See [`crate::crypto::key_check`]. Absent from slots written before it
+    /// was added, and from unwrapped slots of files older than version 2.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_check_b64: Option<String>,
+    #[serde(default)]
+    pub created_ms: u64,
+}
+
+impl KeySlot {
+    pub fn kdf_params(&self) -> KdfParams {
+        KdfParams {
+            iterations: self.kdf_iterations,
+            memory_kib: self.kdf_memory_kib,
+        }
+    }
+
+    /// Bytes bound to the wrapped key: everything about the slot except the
+    /// wrapped key itself, plus the vault it belongs to.
+    pub fn associated_data(&self, vault_id: &str) -> Vec<u8> {
+        let mut slot = serde_json::to_value(self).expect("slot serializes");
+        if let Some(obj) = slot.as_object_mut() {
+            obj.remove("wrapped_key_b64");
+        }
+        serde_json::to_vec(&json!({ "vault_id": vault_id, "slot": slot })).expect("slot serializes")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SlotKind {
+    Password,
+    Keyfile,
+    /// The master password and a keyfile, both required.
+    PasswordKeyfile,
+    /// A secret split into recovery shares.
+    Shamir,
+    /// The recovery key printed in an emergency kit.
+    RecoveryKey,
+}
+
+impl SlotKind {
+    pub fn needs_keyfile(self) -> bool {
+        matches!(self, SlotKind::Keyfile | SlotKind::PasswordKeyfile)
+    }
+}
+
+impl fmt::Display for SlotKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SlotKind::Password => write!(f, "password"),
+            SlotKind::Keyfile => write!(f, "keyfile"),
+            SlotKind::PasswordKeyfile => write!(f, "password+keyfile"),
+            SlotKind::Shamir => write!(f, "recovery shares"),
+            SlotKind::RecoveryKey => write!(f, "recovery key"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EncryptedFile {
+    pub header: Header,
+    pub blob_b64: String,
+    /// Associated data the blob was sealed with, or `None` for files written
+    /// before version 2, which sealed nothing but the plaintext.
+    #[serde(skip)]
+    pub associated_data: Option<Vec<u8>>,
+}
+
+/// What a vault file turned out to contain.
+pub enum VaultDocument {
+    Encrypted(EncryptedFile),
+    /// An unencrypted `Store` written by very early releases.
+    LegacyPlaintext(Value),
+}
+
+/// Upgrades a document from version `i` to `i + 1`, where `i` is its index.
+type Migration = fn(Value) -> Result<Value, LoadError>;
+
+const MIGRATIONS: &[Migration] = &[v0_to_v1, v1_to_v2, v2_to_v3];
+const _: () = assert!(MIGRATIONS.len() == FORMAT_VERSION as usize);
+
+/// Version 0 had no header; the KDF fields and blob sat at the top level and
+/// were always Argon2i and XChaCha20-Poly1305.
+fn v0_to_v1(mut doc: Value) -> Result<Value, LoadError> {
+    let obj = doc
+        .as_object_mut()
+        .ok_or_else(|| LoadError::Corrupt("expected a JSON object".into()))?;
+    obj.insert(
+        "header".into(),
+        json!({
+            "format_version": 1,
+            "cipher": CIPHER_XCHACHA20_POLY1305,
+            "kdf": KDF_ARGON2I,
+        }),
+    );
+    Ok(doc)
+}
+
+/// Version 2 moved the KDF fields into the header and gave every vault an id.
+/// Older files get an id derived from their salt, which they keep from then on.
+fn v1_to_v2(mut doc: Value) -> Result<Value, LoadError> {
+    let obj = doc
+        .as_object_mut()
+        .ok_or_else(|| LoadError::Corrupt("expected a JSON object".into()))?;
+    let mut take = |key: &str| {
+        obj.remove(key)
+            .ok_or_else(|| LoadError::Corrupt(format!("missing {key}")))
+    };
+    let salt_b64 = take("salt_b64")?;
+    let kdf_iterations = take("kdf_iterations")?;
+    let kdf_memory_kib = take("kdf_memory_kib")?;
+    let salt = salt_b64
+        .as_str()
+        .and_then(|s| general_purpose::STANDARD.decode(s).ok())
+        .ok_or_else(|| LoadError::Corrupt("invalid salt".into()))?;
+
+    let header = obj
+        .get_mut("header")
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| LoadError::Corrupt("missing header".into()))?;
+    header.insert("format_version".into(), json!(2));
+    header.insert("vault_id".into(), json!(crate::crypto::hex(&salt)));
+    header.insert("kdf_iterations".into(), kdf_iterations);
+    header.insert("kdf_memory_kib".into(), kdf_memory_kib);
+    header.insert("salt_b64".into(), salt_b64);
+    Ok(doc)
+}
+
+/// Version 3 moved the KDF fields into a list of key slots. A version 2 file
+/// becomes a single password slot whose derived key is used directly.
+fn v2_to_v3(mut doc: Value) -> Result<Value, LoadError> {
+    let header = doc
+        .get_mut("header")
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| LoadError::Corrupt("missing header".into()))?;
+    let mut slot = serde_json::Map::new();
+    slot.insert("id".into(), json!("legacy"));
+    slot.insert("kind".into(), json!(SlotKind::Password));
+    slot.insert("label".into(), json!("master password"));
+    for key in [
+        "kdf",
+        "kdf_iterations",
+        "kdf_memory_kib",
+        "salt_b64",
+        "key_check_b64",
+    ] {
+        if let Some(value) = header.remove(key) {
+            slot.insert(key.into(), value);
+        }
+    }
+    header.insert("format_version".into(), json!(3));
+    header.insert("key_slots".into(), json!([slot]));
+    Ok(doc)
+}
+
+/// The associated data a file of `version` sealed its blob with, given its
+/// header as stored.
+fn stored_associated_data(version: u32, header: &Value) -> Option<Vec<u8>> {
+    let header = match version {
+        0 | 1 => return None,
+        2 => header.clone(),
+        _ => {
+            let mut header = header.clone();
+            if let Some(obj) = header.as_object_mut() {
+                obj.remove("key_slots");
+            }
+            header
+        }
+    };
+    Some(serde_json::to_vec(&header).expect("value serializes"))
+}
+
+fn version_of(doc: &Value) -> Result<Option<u32>, LoadError> {
+    if let Some(header) = doc.get("header") {
+        let version = header
+            .get("format_version")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| LoadError::Corrupt("header has no format_version".into()))?;
+        let version = u32::try_from(version)
+            .map_err(|_| LoadError::UnsupportedFormat(format!("format version {version}")))?;
+        return Ok(Some(version));
+    }
+    if doc.get("blob_b64").is_some() {
+        return Ok(Some(0));
+    }
+    Ok(None)
+}
+
+/// Parses a vault file, migrating it to the current layout if it is older.
+pub fn parse(bytes: &[u8]) -> Result<VaultDocument, LoadError> {
+    let mut doc: Value = serde_json::from_slice(bytes)
+        .map_err(|e| LoadError::Corrupt(format!("not a lockbox vault: {e}")))?;
+
+    let Some(mut version) = version_of(&doc)? else {
+        if doc.get("vault_items").is_some() {
+            return Ok(VaultDocument::LegacyPlaintext(doc));
+        }
+        return Err(LoadError::Corrupt("not a lockbox vault".into()));
+    };
+    if version > FORMAT_VERSION {
+        return Err(LoadError::UnsupportedFormat(format!(
+            "format version {version} is newer than this lockbox supports ({FORMAT_VERSION}); upgrade lockbox"
+        )));
+    }
+    let associated_data = stored_associated_data(version, &doc["header"]);
+    while version < FORMAT_VERSION {
+        doc = MIGRATIONS[version as usize](doc)?;
+        version += 1;
+    }
+
+    let mut enc: EncryptedFile = serde_json::from_value(doc)
+        .map_err(|e| LoadError::Corrupt(format!("invalid vault header: {e}")))?;
+    enc.associated_data = associated_data;
+    if enc.header.cipher != CIPHER_XCHACHA20_POLY1305 {
+        return Err(LoadError::UnsupportedFormat(format!(
+            "cipher {:?}",
+            enc.header.cipher
+        )));
+    }
+    if let Some(slot) = enc.header.key_slots.iter().find(|s| s.kdf != KDF_ARGON2I) {
+        return Err(LoadError::UnsupportedFormat(format!("KDF {:?}", slot.kdf)));
+    }
+    Ok(VaultDocument::Encrypted(enc))
+}
@@ -0,0 +1,42 @@
+//! Random passwords for new entries.
+
+use crate::secret::SecretString;
+use orion::util::secure_rand_bytes;
+
+pub const DEFAULT_LENGTH: usize = 24;
+pub const MIN_LENGTH: usize = 8;
+
+const ALPHABET: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789!#$%&*+-.:;=?@^_~";
+
+/// A password of `length` characters drawn uniformly from letters, digits and
+/// punctuation.
+pub fn password(length: usize) -> anyhow::Result<SecretString> {
+    if length < MIN_LENGTH {
+        anyhow::bail!("generated passwords must be at least {MIN_LENGTH} characters");
+    }
+    // Bytes at or above this would make the first characters more likely.
+    let limit = 256 - 256 % ALPHABET.len();
+    let mut out = String::with_capacity(length);
+    let mut byte = [0u8; 1];
+    while out.len() < length {
+        secure_rand_bytes(&mut byte)?;
+        if usize::from(byte[0]) < limit {
+            out.push(char::from(ALPHABET[usize::from(byte[0]) % ALPHABET.len()]));
+        }
+    }
+    Ok(SecretString::new(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn has_requested_length_and_alphabet() {
+        let p = password(40).unwrap();
+        assert_eq!(p.len(), 40);
+        assert!(p.bytes().all(|b| ALPHABET.contains(&b)));
+        assert!(password(MIN_LENGTH - 1).is_err());
+    }
+}
@@ -0,0 +1,461 @@
+//! [`VaultHandle`]: opening, editing and saving a vault from other programs.
+
+use crate::{
+    crypto,
+    entry::EntryData,
+    error::{Error, LoadError},
+    format::{self, KeySlot, SlotKind, VaultDocument},
+    kdf::KdfParams,
+    keyring::{Credential, Keyring},
+    lock::{LockMode, VaultLock},
+    recovery::{self, RecoveryShare},
+    secret::SecretString,
+    store::{Store, Vault},
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use zeroize::Zeroizing;
+
+/// A vault file, locked against other lockbox processes for as long as the
+/// handle lives.
+///
+/// A handle starts out locked: only [`unlock`](Self::unlock) decrypts the
+/// entries, and [`lock`](Self::lock) forgets them again. Changes stay in
+/// memory until [`save`](Self::save), which needs the handle to have been
+/// opened with [`LockMode::Exclusive`].
+#[derive(Debug)]
+pub struct VaultHandle {
+    path: PathBuf,
+    mode: LockMode,
+    opened: Option<(Store, Keyring)>,
+    _lock: VaultLock,
+}
+
+impl VaultHandle {
+    /// Takes the lock on the existing vault at `path`, waiting up to
+    /// `timeout` for other processes to release it.
+    pub fn open(
+        path: impl Into<PathBuf>,
+        mode: LockMode,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        let path = path.into();
+        if !path.exists() {
+            return Err(LoadError::NotFound(path).into());
+        }
+        let lock = VaultLock::acquire(&path, mode, timeout)?;
+        Ok(Self {
+            path,
+            mode,
+            opened: None,
+            _lock: lock,
+        })
+    }
+
+    /// Creates an empty vault at `path` with a single key slot for
+    /// `credential`, and returns it unlocked and exclusively locked.
+    pub fn create(
+        path: impl Into<PathBuf>,
+        credential: &Credential,
+        params: KdfParams,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        let path = path.into();
+        let lock = VaultLock::acquire(&path, LockMode::Exclusive, timeout)?;
+        if path.exists() {
+            return Err(Error::AlreadyExists(path));
+        }
+        let mut keyring = Keyring::new(crypto::random_id()?);
+        let label = match credential.kind() {
+            SlotKind::Keyfile => "keyfile",
+            _ => "master password",
+        };
+        keyring.add_slot(credential, params, label)?;
+        let handle = Self {
+            path,
+            mode: LockMode::Exclusive,
+            opened: Some((Store::new(), keyring)),
+            _lock: lock,
+        };
+        handle.save()?;
+        Ok(handle)
+    }
+
+    /// Decrypts the vault with `credential`, replacing anything unsaved.
+    pub fn unlock(&mut self, credential: &Credential) -> Result<(), Error> {
+        self.opened = Some(Store::load(&self.path, credential)?);
+        Ok(())
+    }
+
+    /// Forgets the decrypted entries and keys, discarding unsaved changes.
+    /// The file lock is kept.
+    pub fn lock(&mut self) {
+        self.opened = None;
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.opened.is_some()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn entries(&self) -> Result<&[Vault], Error> {
+        Ok(&self.store()?.vault_items)
+    }
+
+    pub fn entry(&self, id: usize) -> Result<&Vault, Error> {
+        self.entries()?
+            .iter()
+            .find(|v| v.id == id)
+            .ok_or(Error::NoSuchEntry(id))
+    }
+
+    /// The entry `query` names: the one with that id, else the one with that
+    /// name, compared exactly and then ignoring case.
+    pub fn find_entry(&self, query: &str) -> Result<&Vault, Error> {
+        let entries = self.entries()?;
+        if let Some(entry) = query
+            .parse::<usize>()
+            .ok()
+            .and_then(|id| entries.iter().find(|v| v.id == id))
+        {
+            return Ok(entry);
+        }
+        let named = |same: fn(&str, &str) -> bool| {
+            entries
+                .iter()
+                .filter(|v| same(&v.service, query))
+                .collect::<Vec<_>>()
+        };
+        let mut found = named(|a, b| a == b);
+        if found.is_empty() {
+            found = named(|a, b| a.to_lowercase() == b.to_lowercase());
+        }
+        match found[..] {
+            [] => Err(Error::NoMatch(query.to_owned())),
+            [entry] => Ok(entry),
+            _ => Err(Error::Ambiguous {
+                query: query.to_owned(),
+                ids: found.iter().map(|v| v.id).collect(),
+            }),
+        }
+    }
+
+    pub fn entry_mut(&mut self, id: usize) -> Result<&mut Vault, Error> {
+        self.store_mut()?
+            .vault_items
+            .iter_mut()
+            .find(|v| v.id == id)
+            .ok_or(Error::NoSuchEntry(id))
+    }
+
+    /// Adds a login and returns its id.
+    pub fn add_entry(
+        &mut self,
+        service: String,
+        username: String,
+        password: SecretString,
+    ) -> Result<usize, Error> {
+        self.add_typed_entry(service, EntryData::Login { username, password })
+    }
+
+    /// Adds an entry of any type once its name and values pass the checks of
+    /// [`Vault::validate`], and returns its id.
+    pub fn add_typed_entry(&mut self, service: String, data: EntryData) -> Result<usize, Error> {
+        let store = self.store_mut()?;
+        let id = store.next_id;
+        let entry = Vault::with_data(id, service, data);
+        entry.validate()?;
+        store.next_id += 1;
+        store.vault_items.push(entry);
+        Ok(id)
+    }
+
+    /// Changes an entry's values once they pass their type's checks, keeping
+    /// the old ones in its history.
+    pub fn update_entry(&mut self, id: usize, data: EntryData) -> Result<(), Error> {
+        data.validate()?;
+        let policy = self.store()?.settings.history;
+        self.entry_mut(id)?.replace_data(data, &policy);
+        Ok(())
+    }
+
+    /// Replaces an entry's name, values and details with those of `edited`
+    /// once they pass their checks. The id and creation time stay, the old
+    /// values join the history, and the modified time is only updated if
+    /// something changed, which is what is returned.
+    pub fn edit_entry(&mut self, id: usize, edited: Vault) -> Result<bool, Error> {
+        edited.validate()?;
+        let policy = self.store()?.settings.history;
+        Ok(self.entry_mut(id)?.update_from(edited, &policy))
+    }
+
+    /// Brings back earlier value `number` of an entry, 1 being the most
+    /// recent, as listed by its `history`.
+    pub fn restore_entry_version(&mut self, id: usize, number: usize) -> Result<(), Error> {
+        let policy = self.store()?.settings.history;
+        let entry = self.entry_mut(id)?;
+        number
+            .checked_sub(1)
+            .and_then(|index| entry.restore_version(index, &policy))
+            .ok_or(Error::NoSuchVersion { id, number })
+    }
+
+    /// Records that a secret of an entry was just read, without counting it
+    /// as a change.
+    pub fn mark_used(&mut self, id: usize) -> Result<(), Error> {
+        self.entry_mut(id)?.mark_used();
+        Ok(())
+    }
+
+    pub fn remove_entry(&mut self, id: usize) -> Result<Vault, Error> {
+        let items = &mut self.store_mut()?.vault_items;
+        let pos = items
+            .iter()
+            .position(|v| v.id == id)
+            .ok_or(Error::NoSuchEntry(id))?;
+        Ok(items.remove(pos))
+    }
+
+    /// The KDF parameters of the key slot the vault was opened through.
+    pub fn unlocked_kdf_params(&self) -> Result<KdfParams, Error> {
+        Ok(self
+            .keyring()?
+            .unlocked_slot()
+            .map(|s| s.kdf_params())
+            .unwrap_or_default())
+    }
+
+    /// Re-wraps the slot the vault was opened through if it is from an older
+    /// release or its KDF cost is below `min`, so the next save upgrades it.
+    /// Returns whether it did; a read-only handle or a cached key, which
+    /// cannot derive a new key, leaves the slot as it is.
+    pub fn upgrade_unlocked_slot(
+        &mut self,
+        credential: &Credential,
+        min: &KdfParams,
+    ) -> Result<bool, Error> {
+        let params = self.unlocked_kdf_params()?;
+        let writable = self.mode == LockMode::Exclusive;
+        let keyring = self.keyring_mut()?;
+        if !writable
+            || matches!(credential, Credential::Cached(_))
+            || !(keyring.is_legacy() || params.is_below(min))
+        {
+            return Ok(false);
+        }
+        keyring.rewrap_unlocked_slot(credential, params.at_least(min))?;
+        Ok(true)
+    }
+
+    /// Re-wraps the slot the vault was opened through under `params`.
+    /// `credential` must be the one it was opened with.
+    pub fn set_kdf(&mut self, credential: &Credential, params: KdfParams) -> Result<(), Error> {
+        self.keyring_mut()?
+            .rewrap_unlocked_slot(credential, params)?;
+        Ok(())
+    }
+
+    /// Switches to a new data key wrapped under `credential` in the slot the
+    /// vault was opened through, keeping its id, label and KDF cost. The
+    /// other slots hold the old key, so they are revoked and returned.
+    pub fn change_credential(&mut self, credential: &Credential) -> Result<Vec<KeySlot>, Error> {
+        let params = self.unlocked_kdf_params()?;
+        Ok(self
+            .keyring_mut()?
+            .rotate_unlocked_slot(credential, params)?)
+    }
+
+    /// Wraps the data key under `credential` in the slot the vault was opened
+    /// through, keeping its id, label and KDF cost. The data key stays, and
+    /// with it every other slot.
+    pub fn rewrap_credential(&mut self, credential: &Credential) -> Result<(), Error> {
+        let params = self.unlocked_kdf_params()?;
+        self.set_kdf(credential, params)
+    }
+
+    /// Adds a key slot that opens the vault with `credential`.
+    pub fn add_slot(
+        &mut self,
+        credential: &Credential,
+        params: KdfParams,
+        label: &str,
+    ) -> Result<KeySlot, Error> {
+        Ok(self
+            .keyring_mut()?
+            .add_slot(credential, params, label)?
+            .clone())
+    }
+
+    pub fn remove_slot(&mut self, id: &str) -> Result<KeySlot, Error> {
+        Ok(self.keyring_mut()?.remove_slot(id)?)
+    }
+
+    /// Adds a key slot for a new random secret and splits it into `shares`
+    /// recovery shares, any `threshold` of which open the slot.
+    pub fn add_recovery_shares(
+        &mut self,
+        threshold: u8,
+        shares: u8,
+        params: KdfParams,
+    ) -> Result<Vec<RecoveryShare>, Error> {
+        let secret = recovery::new_secret()?;
+        let label = format!("recovery shares, {threshold} of {shares}");
+        let slot = self.add_slot(&Credential::Recovery(secret.clone()), params, &label)?;
+        Ok(recovery::split(&slot.id, &secret, threshold, shares)?)
+    }
+
+    /// Adds a key slot for the recovery key `secret` of a new emergency kit,
+    /// and revokes and returns the slots of earlier kits.
+    pub fn replace_recovery_key(
+        &mut self,
+        secret: &Zeroizing<Vec<u8>>,
+        params: KdfParams,
+    ) -> Result<(KeySlot, Vec<KeySlot>), Error> {
+        let credential = Credential::RecoveryKey(secret.clone());
+        let slot = self.add_slot(&credential, params, "emergency kit")?;
+        let earlier = self
+            .keyring()?
+            .slots()
+            .iter()
+            .filter(|s| s.kind == SlotKind::RecoveryKey && s.id != slot.id)
+            .map(|s| s.id.clone())
+            .collect::<Vec<_>>();
+        let revoked = earlier
+            .iter()
+            .map(|id| self.remove_slot(id))
+            .collect::<Result<_, _>>()?;
+        Ok((slot, revoked))
+    }
+
+    /// Unlocks the vault with the secret rebuilt from recovery shares, and
+    /// returns the id of the slot they open.
+    pub fn unlock_with_shares(&mut self, shares: Vec<RecoveryShare>) -> Result<String, Error> {
+        let Some(slot_id) = shares.first().map(|s| s.slot_id.clone()) else {
+            return Err(anyhow::anyhow!("no shares given").into());
+        };
+        let bytes = fs::read(&self.path).map_err(LoadError::Io)?;
+        if let VaultDocument::Encrypted(enc) = format::parse(&bytes)?
+            && !enc.header.key_slots.iter().any(|s| s.id == slot_id)
+        {
+            return Err(anyhow::anyhow!(
+                "these shares open key slot {slot_id}, which has been removed from the vault"
+            )
+            .into());
+        }
+        let secret = recovery::combine(shares)?;
+        self.unlock(&Credential::Recovery(secret))?;
+        Ok(slot_id)
+    }
+
+    /// Switches to a new data key wrapped under `credential` alone, as after
+    /// recovering a vault whose password was lost. Every earlier slot holds
+    /// the old key, so they are all revoked and returned.
+    pub fn reset_credentials(
+        &mut self,
+        credential: &Credential,
+        params: KdfParams,
+        label: &str,
+    ) -> Result<Vec<KeySlot>, Error> {
+        let keyring = self.keyring_mut()?;
+        let revoked = keyring.rotate();
+        keyring.add_slot(credential, params, label)?;
+        Ok(revoked)
+    }
+
+    /// Encrypts the vault and writes it, keeping a backup of the previous
+    /// file. Returns the temporary files of interrupted saves that were
+    /// cleared away first.
+    pub fn save(&self) -> Result<Vec<PathBuf>, Error> {
+        let (store, keyring) = self.writable()?;
+        store.save(&self.path, keyring)
+    }
+
+    /// Like [`save`](Self::save), but keeps no backup, for changes as slight
+    /// as [`mark_used`](Self::mark_used) that would otherwise push real ones
+    /// out of the backups kept.
+    pub fn save_without_backup(&self) -> Result<Vec<PathBuf>, Error> {
+        let (store, keyring) = self.writable()?;
+        store.save_without_backup(&self.path, keyring)
+    }
+
+    fn writable(&self) -> Result<&(Store, Keyring), Error> {
+        if self.mode != LockMode::Exclusive {
+            return Err(Error::ReadOnly);
+        }
+        self.opened.as_ref().ok_or(Error::Locked)
+    }
+
+    /// The decrypted contents, for what the entry methods don't cover.
+    pub fn store(&self) -> Result<&Store, Error> {
+        self.opened.as_ref().map(|(s, _)| s).ok_or(Error::Locked)
+    }
+
+    pub fn store_mut(&mut self) -> Result<&mut Store, Error> {
+        self.opened.as_mut().map(|(s, _)| s).ok_or(Error::Locked)
+    }
+
+    /// The data key and key slots, for managing how the vault is unlocked.
+    pub fn keyring(&self) -> Result<&Keyring, Error> {
+        self.opened.as_ref().map(|(_, k)| k).ok_or(Error::Locked)
+    }
+
+    pub fn keyring_mut(&mut self) -> Result<&mut Keyring, Error> {
+        self.opened.as_mut().map(|(_, k)| k).ok_or(Error::Locked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> KdfParams {
+        KdfParams {
+            iterations: 3,
+            memory_kib: 64,
+        }
+    }
+
+    #[test]
+    fn locked_and_read_only_handles_refuse() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.json");
+        let password = Credential::Password("correct horse".to_owned().into());
+        let mut handle = VaultHandle::create(&path, &password, params(), Duration::ZERO).unwrap();
+        handle.lock();
+        assert!(matches!(handle.entries(), Err(Error::Locked)));
+        assert!(matches!(handle.save(), Err(Error::Locked)));
+        drop(handle);
+
+        let mut reader = VaultHandle::open(&path, LockMode::Shared, Duration::ZERO).unwrap();
+        reader.unlock(&password).unwrap();
+        reader
+            .add_entry("mail".into(), "me".into(), "pw".to_owned().into())
+            .unwrap();
+        assert!(matches!(reader.save(), Err(Error::ReadOnly)));
+        assert!(matches!(reader.remove_entry(9), Err(Error::NoSuchEntry(9))));
+    }
+
+    #[test]
+    fn nameless_entries_and_failed_writes_are_typed_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.json");
+        let password = Credential::Password("correct horse".to_owned().into());
+        let mut handle = VaultHandle::create(&path, &password, params(), Duration::ZERO).unwrap();
+        assert!(matches!(
+            handle.add_entry(" ".into(), "me".into(), "pw".to_owned().into()),
+            Err(Error::Invalid(_))
+        ));
+        assert!(handle.entries().unwrap().is_empty());
+
+        fs::remove_dir_all(dir.path()).unwrap();
+        let error = handle.save().unwrap_err();
+        assert!(matches!(error, Error::Io(_)), "{error:?}");
+        assert_eq!(error.exit_code(), 74);
+    }
+}
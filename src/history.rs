@@ -0,0 +1,96 @@
+//! Earlier values of entries, kept so a half-finished password change can be
+//! undone.
+
+use crate::entry::EntryData;
+use serde::{Deserialize, Serialize};
+
+const DAY_MS: u64 = 86_400_000;
+
+/// What an entry held until it was changed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PastData {
+    /// Milliseconds since the epoch when the value was replaced.
+    pub replaced_ms: u64,
+    pub data: EntryData,
+}
+
+/// How many earlier values each entry keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryPolicy {
+    /// Keep at most this many per entry; 0 keeps none.
+    pub keep_last: usize,
+    /// Forget values replaced more than this many days ago; 0 never does.
+    pub max_age_days: u64,
+}
+
+impl Default for HistoryPolicy {
+    fn default() -> Self {
+        Self {
+            keep_last: 10,
+            max_age_days: 0,
+        }
+    }
+}
+
+impl HistoryPolicy {
+    /// Drops what the policy no longer keeps from `history`, newest first.
+    pub fn prune(&self, history: &mut Vec<PastData>, now_ms: u64) {
+        history.truncate(self.keep_last);
+        if self.max_age_days > 0 {
+            let cutoff = now_ms.saturating_sub(self.max_age_days.saturating_mul(DAY_MS));
+            history.retain(|past| past.replaced_ms >= cutoff);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn past(replaced_ms: u64) -> PastData {
+        PastData {
+            replaced_ms,
+            data: EntryData::Login {
+                username: "me".into(),
+                password: replaced_ms.to_string().into(),
+            },
+        }
+    }
+
+    #[test]
+    fn prunes_by_count_and_age() {
+        let now = 100 * DAY_MS;
+        let mut history = vec![
+            past(now - DAY_MS),
+            past(now - 5 * DAY_MS),
+            past(now - 40 * DAY_MS),
+        ];
+
+        HistoryPolicy {
+            keep_last: 10,
+            max_age_days: 30,
+        }
+        .prune(&mut history, now);
+        assert_eq!(history.len(), 2);
+
+        HistoryPolicy {
+            keep_last: 1,
+            max_age_days: 0,
+        }
+        .prune(&mut history, now);
+        assert_eq!(history, vec![past(now - DAY_MS)]);
+    }
+
+    #[test]
+    fn huge_ages_keep_everything() {
+        let now = 100 * DAY_MS;
+        let mut history = vec![past(now - DAY_MS), past(0)];
+        HistoryPolicy {
+            keep_last: 10,
+            max_age_days: u64::MAX,
+        }
+        .prune(&mut history, now);
+        assert_eq!(history.len(), 2);
+    }
+}
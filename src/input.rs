@@ -0,0 +1,203 @@
+//! Reading secrets from somewhere other than the terminal, and choosing
+//! where the master password comes from.
+//!
+//! Unless a running agent holds the vault's key, the master password is taken
+//! from the first of these that is set:
+//!
+//! 1. `--master-password-file PATH`, the whole file, or
+//!    `--master-password-fd N`, an inherited descriptor read to its end;
+//! 2. `LOCKBOX_MASTER_PASSWORD_CMD`, the output of a command run with `sh -c`;
+//! 3. `--pinentry PROGRAM`, a pinentry program;
+//! 4. the terminal.
+//!
+//! One trailing newline is dropped from the first two. New passwords and a
+//! recovery key are only asked for through the last two, except that `init`
+//! and `migrate-plaintext` take the password of the vault they create from
+//! the first two.
+
+use crate::pinentry;
+use lockbox::secret::SecretString;
+use rpassword::prompt_password;
+use std::{
+    cell::OnceCell,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use zeroize::Zeroizing;
+
+/// The environment variable holding a command that prints the master password.
+pub const MASTER_PASSWORD_CMD_ENV: &str = "LOCKBOX_MASTER_PASSWORD_CMD";
+
+/// A master password given without asking for it.
+#[derive(Debug)]
+pub enum GivenMaster {
+    File(PathBuf),
+    Fd(i32),
+    Command(String),
+}
+
+impl GivenMaster {
+    fn read(&self) -> anyhow::Result<SecretString> {
+        match self {
+            Self::File(path) => secret_from_file(path),
+            Self::Fd(fd) => secret_from_fd(*fd),
+            Self::Command(command) => secret_from_command(command),
+        }
+    }
+}
+
+/// Where secrets typed by the user come from; see the module docs.
+#[derive(Debug, Default)]
+pub struct Prompt {
+    given: Option<GivenMaster>,
+    pinentry: Option<PathBuf>,
+    /// A descriptor can only be read once, so the master password is kept for
+    /// commands that need it twice.
+    master: OnceCell<SecretString>,
+}
+
+impl Prompt {
+    pub fn new(given: Option<GivenMaster>, pinentry: Option<PathBuf>) -> Self {
+        Self {
+            given,
+            pinentry,
+            master: OnceCell::new(),
+        }
+    }
+
+    /// The master password of an existing vault.
+    pub fn master(&self) -> anyhow::Result<SecretString> {
+        match self.given_master() {
+            Some(master) => master,
+            None => self.ask("Master password"),
+        }
+    }
+
+    /// The master password, if one was given without asking for it.
+    pub fn given_master(&self) -> Option<anyhow::Result<SecretString>> {
+        let given = self.given.as_ref()?;
+        if let Some(master) = self.master.get() {
+            return Some(Ok(master.clone()));
+        }
+        Some(
+            given
+                .read()
+                .map(|master| self.master.get_or_init(|| master).clone()),
+        )
+    }
+
+    /// Asks the user for a secret through pinentry or the terminal.
+    pub fn ask(&self, prompt: &str) -> anyhow::Result<SecretString> {
+        match &self.pinentry {
+            Some(program) => pinentry::get_pin(program, &format!("lockbox: {prompt}"), prompt),
+            None => Ok(SecretString::new(prompt_password(format!("{prompt}: "))?)),
+        }
+    }
+}
+
+/// Reads all of standard input as one secret.
+pub fn secret_from_stdin() -> anyhow::Result<SecretString> {
+    let mut bytes = vec![];
+    io::stdin().read_to_end(&mut bytes)?;
+    secret_from_bytes(bytes, "standard input")
+}
+
+pub fn secret_from_file(path: &Path) -> anyhow::Result<SecretString> {
+    let bytes =
+        fs::read(path).map_err(|e| anyhow::anyhow!("unable to read {}: {e}", path.display()))?;
+    secret_from_bytes(bytes, &path.display().to_string())
+}
+
+/// Reads an inherited file descriptor to its end as one secret.
+#[cfg(unix)]
+pub fn secret_from_fd(fd: i32) -> anyhow::Result<SecretString> {
+    use std::os::fd::FromRawFd;
+    // SAFETY: fcntl only inspects the descriptor. If it is open, it was
+    // handed to us for this purpose and nothing else in the process uses it;
+    // it is closed once read.
+    if fd < 0 || unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        anyhow::bail!("file descriptor {fd} is not open");
+    }
+    let mut file = unsafe { fs::File::from_raw_fd(fd) };
+    let mut bytes = vec![];
+    file.read_to_end(&mut bytes)
+        .map_err(|e| anyhow::anyhow!("unable to read file descriptor {fd}: {e}"))?;
+    secret_from_bytes(bytes, &format!("file descriptor {fd}"))
+}
+
+#[cfg(not(unix))]
+pub fn secret_from_fd(_fd: i32) -> anyhow::Result<SecretString> {
+    anyhow::bail!("reading a password from a file descriptor needs a Unix system")
+}
+
+/// Runs `command` with `sh -c` and takes its output as one secret.
+pub fn secret_from_command(command: &str) -> anyhow::Result<SecretString> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| anyhow::anyhow!("unable to run {MASTER_PASSWORD_CMD_ENV}: {e}"))?;
+    let stdout = output.stdout;
+    if !output.status.success() {
+        drop(Zeroizing::new(stdout));
+        anyhow::bail!("{MASTER_PASSWORD_CMD_ENV} failed: {}", output.status);
+    }
+    secret_from_bytes(stdout, &format!("the output of {MASTER_PASSWORD_CMD_ENV}"))
+}
+
+/// Turns the contents of a file or stream into a secret, dropping a single
+/// trailing newline as most editors and `echo` add one.
+fn secret_from_bytes(mut bytes: Vec<u8>, source: &str) -> anyhow::Result<SecretString> {
+    if bytes.ends_with(b"\n") {
+        bytes.pop();
+        if bytes.ends_with(b"\r") {
+            bytes.pop();
+        }
+    }
+    let secret = match String::from_utf8(bytes) {
+        Ok(s) => SecretString::new(s),
+        Err(e) => {
+            drop(Zeroizing::new(e.into_bytes()));
+            anyhow::bail!("{source} is not valid UTF-8");
+        }
+    };
+    if secret.is_empty() {
+        anyhow::bail!("{source} is empty");
+    }
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_one_trailing_newline() {
+        let s = secret_from_bytes(b"pass word\r\n".to_vec(), "test").unwrap();
+        assert_eq!(&*s, "pass word");
+        let s = secret_from_bytes(b"two\n\n".to_vec(), "test").unwrap();
+        assert_eq!(&*s, "two\n");
+        assert!(secret_from_bytes(b"\n".to_vec(), "test").is_err());
+    }
+
+    #[test]
+    fn given_master_is_read_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("master");
+        fs::write(&path, "from file\n").unwrap();
+        let prompt = Prompt::new(Some(GivenMaster::File(path.clone())), None);
+
+        assert_eq!(&*prompt.master().unwrap(), "from file");
+        fs::remove_file(&path).unwrap();
+        assert_eq!(&*prompt.master().unwrap(), "from file");
+    }
+
+    #[test]
+    fn command_output_is_the_secret_and_failure_is_an_error() {
+        assert_eq!(&*secret_from_command("printf 'a b\\n'").unwrap(), "a b");
+        assert!(secret_from_command("echo leaked; exit 3").is_err());
+    }
+}
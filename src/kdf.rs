@@ -0,0 +1,124 @@
+//! Argon2i parameters for deriving the vault key from the master password.
+
+use orion::kdf::{self, Password, Salt, SecretKey};
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+
+/// Lower bound on iterations imposed by orion's Argon2i.
+pub const MIN_ITERATIONS: u32 = 3;
+/// Upper bound on iterations accepted from a vault file.
+pub const MAX_ITERATIONS: u32 = 64;
+/// Upper bound on memory accepted from a vault file (2 GiB).
+pub const MAX_MEMORY_KIB: u32 = 1 << 21;
+/// Lower bound on memory imposed by Argon2 itself.
+pub const MIN_MEMORY_KIB: u32 = 8;
+
+/// Environment variables that raise the minimum parameters a vault is saved with.
+pub const MIN_ITERATIONS_ENV: &str = "LOCKBOX_KDF_MIN_ITERATIONS";
+pub const MIN_MEMORY_KIB_ENV: &str = "LOCKBOX_KDF_MIN_MEMORY_KIB";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub iterations: u32,
+    pub memory_kib: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            iterations: 3,
+            memory_kib: 1 << 16,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Minimum parameters a vault may be saved with. Defaults to 3 iterations
+    /// over 19 MiB unless overridden through [`MIN_ITERATIONS_ENV`] /
+    /// [`MIN_MEMORY_KIB_ENV`].
+    pub fn minimum() -> anyhow::Result<Self> {
+        let read = |name: &str, default: u32| -> anyhow::Result<u32> {
+            match env::var(name) {
+                Ok(v) => v
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("{name} must be a positive integer, got {v:?}")),
+                Err(_) => Ok(default),
+            }
+        };
+        let min = Self {
+            iterations: read(MIN_ITERATIONS_ENV, MIN_ITERATIONS)?,
+            memory_kib: read(MIN_MEMORY_KIB_ENV, 19 * 1024)?,
+        };
+        if !min.is_within_bounds() {
+            anyhow::bail!(
+                "configured KDF minimum (iterations={}, memory_kib={}) is out of range",
+                min.iterations,
+                min.memory_kib
+            );
+        }
+        Ok(min)
+    }
+
+    /// Whether these parameters are sane enough to run. Files outside these
+    /// bounds are rejected before any memory is allocated for Argon2.
+    pub fn is_within_bounds(&self) -> bool {
+        (MIN_ITERATIONS..=MAX_ITERATIONS).contains(&self.iterations)
+            && (MIN_MEMORY_KIB..=MAX_MEMORY_KIB).contains(&self.memory_kib)
+    }
+
+    pub fn is_below(&self, min: &KdfParams) -> bool {
+        self.iterations < min.iterations || self.memory_kib < min.memory_kib
+    }
+
+    /// Raises each parameter to at least the one in `min`.
+    pub fn at_least(self, min: &KdfParams) -> Self {
+        Self {
+            iterations: self.iterations.max(min.iterations),
+            memory_kib: self.memory_kib.max(min.memory_kib),
+        }
+    }
+
+    pub fn derive_key(
+        &self,
+        password: &Password,
+        salt: &Salt,
+    ) -> Result<SecretKey, orion::errors::UnknownCryptoError> {
+        kdf::derive_key(password, salt, self.iterations, self.memory_kib, 32)
+    }
+}
+
+/// Benchmarks Argon2 on this machine and picks parameters that take roughly
+/// `target` to derive a key. Memory starts at `memory_kib` and is halved, down
+/// to `min`, only if the fewest allowed iterations already exceed the target.
+pub fn calibrate(
+    target: Duration,
+    memory_kib: u32,
+    min: &KdfParams,
+) -> anyhow::Result<(KdfParams, Duration)> {
+    let password = Password::from_slice(b"lockbox calibration")?;
+    let salt = Salt::default();
+    let time = |params: &KdfParams| -> anyhow::Result<Duration> {
+        let start = Instant::now();
+        params.derive_key(&password, &salt)?;
+        Ok(start.elapsed())
+    };
+
+    let mut params = KdfParams {
+        iterations: min.iterations,
+        memory_kib: memory_kib.clamp(min.memory_kib, MAX_MEMORY_KIB),
+    };
+    let mut elapsed = time(&params)?;
+    while elapsed > target && params.memory_kib / 2 >= min.memory_kib {
+        params.memory_kib /= 2;
+        elapsed = time(&params)?;
+    }
+
+    let per_iteration = elapsed.as_secs_f64() / f64::from(params.iterations);
+    let iterations = target.as_secs_f64() / per_iteration.max(f64::EPSILON);
+    params.iterations = (iterations.floor() as u32).clamp(min.iterations, MAX_ITERATIONS);
+    let measured = time(&params)?;
+    Ok((params, measured))
+}
@@ -0,0 +1,55 @@
+//! Keyfiles: a file of random bytes that unlocks a vault on its own or
+//! together with the master password.
+//!
+//! Only a BLAKE2b-256 digest of the file is ever used, so any file can serve
+//! as a keyfile, but `lockbox keyfile generate` is the way to get a good one.
+
+use orion::{hash, util::secure_rand_bytes};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+use zeroize::Zeroizing;
+
+/// Size of a generated keyfile.
+const GENERATED_LEN: usize = 64;
+
+pub struct Keyfile {
+    digest: Zeroizing<Vec<u8>>,
+}
+
+impl Keyfile {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let bytes = Zeroizing::new(fs::read(path)?);
+        if bytes.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "keyfile is empty",
+            ));
+        }
+        let digest = hash::digest(&bytes).map_err(io::Error::other)?;
+        Ok(Self {
+            digest: Zeroizing::new(digest.as_ref().to_vec()),
+        })
+    }
+
+    pub fn digest(&self) -> &[u8] {
+        &self.digest
+    }
+}
+
+/// Writes a new keyfile of random bytes to `path`, refusing to overwrite an
+/// existing file.
+pub fn generate(path: &Path) -> io::Result<()> {
+    let mut bytes = Zeroizing::new([0u8; GENERATED_LEN]);
+    secure_rand_bytes(bytes.as_mut()).map_err(io::Error::other)?;
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o400);
+    let mut f = options.open(path)?;
+    f.write_all(bytes.as_ref())?;
+    f.sync_all()
+}
@@ -0,0 +1,458 @@
+//! The data key of an open vault and the key slots that wrap it.
+//!
+//! A vault's entries are sealed under a random data key. Each [`KeySlot`] in
+//! the header holds a copy of that key sealed under a key derived from one
+//! unlock secret, so any slot can open the vault and slots can be added or
+//! revoked without re-keying the data.
+
+use crate::{
+    crypto,
+    error::LoadError,
+    format::{KDF_ARGON2I, KeySlot, SlotKind},
+    kdf::KdfParams,
+    keyfile::Keyfile,
+    secret::{SecretBuf, SecretString},
+    timestamp,
+};
+use base64::{Engine, engine::general_purpose};
+use orion::{
+    aead::SecretKey,
+    kdf::{Password, Salt},
+};
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// The secrets presented to unlock a vault. Each variant opens the slots of
+/// one [`SlotKind`].
+pub enum Credential {
+    Password(SecretString),
+    Keyfile(Keyfile),
+    PasswordAndKeyfile(SecretString, Keyfile),
+    /// The secret rebuilt from recovery shares.
+    Recovery(Zeroizing<Vec<u8>>),
+    /// The recovery key from an emergency kit.
+    RecoveryKey(Zeroizing<Vec<u8>>),
+    /// A key derived earlier, as held by the agent.
+    Cached(SlotKey),
+}
+
+/// The key derived for one slot. It only opens the slot with the salt it was
+/// derived with, so a slot re-wrapped since no longer matches.
+pub struct SlotKey {
+    pub kind: SlotKind,
+    pub salt_b64: String,
+    pub key: SecretKey,
+}
+
+impl SlotKey {
+    fn new(slot: &KeySlot, key: &SecretKey) -> Self {
+        Self {
+            kind: slot.kind,
+            salt_b64: slot.salt_b64.clone(),
+            key: SecretKey::from_slice(key.unprotected_as_bytes()).expect("same length"),
+        }
+    }
+}
+
+impl Credential {
+    pub fn kind(&self) -> SlotKind {
+        match self {
+            Credential::Password(_) => SlotKind::Password,
+            Credential::Keyfile(_) => SlotKind::Keyfile,
+            Credential::PasswordAndKeyfile(..) => SlotKind::PasswordKeyfile,
+            Credential::Recovery(_) => SlotKind::Shamir,
+            Credential::RecoveryKey(_) => SlotKind::RecoveryKey,
+            Credential::Cached(k) => k.kind,
+        }
+    }
+
+    fn may_open(&self, slot: &KeySlot) -> bool {
+        match self {
+            Credential::Cached(k) => k.kind == slot.kind && k.salt_b64 == slot.salt_b64,
+            _ => self.kind() == slot.kind,
+        }
+    }
+
+    fn password(&self) -> Option<&str> {
+        match self {
+            Credential::Password(p) | Credential::PasswordAndKeyfile(p, _) => Some(p),
+            Credential::Keyfile(_)
+            | Credential::Recovery(_)
+            | Credential::RecoveryKey(_)
+            | Credential::Cached(_) => None,
+        }
+    }
+
+    fn wrong(&self) -> LoadError {
+        match self {
+            Credential::Password(_) | Credential::Cached(_) => LoadError::WrongPassword,
+            Credential::Keyfile(_) | Credential::PasswordAndKeyfile(..) => LoadError::WrongKeyfile,
+            Credential::Recovery(_) | Credential::RecoveryKey(_) => LoadError::WrongRecoveryKey,
+        }
+    }
+
+    /// The KDF input. A keyfile's digest has a fixed length, so appending it
+    /// to the password is unambiguous.
+    fn secret(&self) -> SecretBuf {
+        let mut secret = SecretBuf::default();
+        if let Some(password) = self.password() {
+            secret.extend_from_slice(password.as_bytes());
+        }
+        if let Credential::Keyfile(k) | Credential::PasswordAndKeyfile(_, k) = self {
+            secret.extend_from_slice(k.digest());
+        }
+        if let Credential::Recovery(key) | Credential::RecoveryKey(key) = self {
+            secret.extend_from_slice(key);
+        }
+        secret
+    }
+
+    fn derive(&self, slot: &KeySlot) -> Result<SecretKey, LoadError> {
+        if let Credential::Cached(k) = self {
+            if !self.may_open(slot) {
+                return Err(self.wrong());
+            }
+            return Ok(SlotKey::new(slot, &k.key).key);
+        }
+        let params = slot.kdf_params();
+        let unsupported = || LoadError::UnsupportedKdf {
+            iterations: params.iterations,
+            memory_kib: params.memory_kib,
+        };
+        if !params.is_within_bounds() {
+            return Err(unsupported());
+        }
+        let salt = general_purpose::STANDARD
+            .decode(&slot.salt_b64)
+            .ok()
+            .and_then(|bytes| Salt::from_slice(&bytes).ok())
+            .ok_or_else(|| LoadError::Corrupt(format!("invalid salt in key slot {}", slot.id)))?;
+        let password = Password::from_slice(&self.secret()).map_err(|_| self.wrong())?;
+        let derived = params
+            .derive_key(&password, &salt)
+            .map_err(|_| unsupported())?;
+        SecretKey::from_slice(derived.unprotected_as_bytes())
+            .map_err(|_| LoadError::Corrupt("derived key has invalid length".into()))
+    }
+}
+
+pub struct Keyring {
+    vault_id: String,
+    data_key: SecretKey,
+    slots: Vec<KeySlot>,
+    /// The slot the vault was opened through, if any.
+    unlocked_slot: Option<String>,
+    /// Whether the data key is known to be right, so that a blob that fails
+    /// to open points at tampering rather than at a wrong secret.
+    verified: bool,
+    /// The derived key of the unlocked slot, for the agent.
+    slot_key: Option<SlotKey>,
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyring")
+            .field("vault_id", &self.vault_id)
+            .field("data_key", &"<redacted>")
+            .field("slots", &self.slots)
+            .field("unlocked_slot", &self.unlocked_slot)
+            .finish()
+    }
+}
+
+impl Keyring {
+    /// A fresh random data key with no slots yet.
+    pub fn new(vault_id: String) -> Self {
+        Self {
+            vault_id,
+            data_key: SecretKey::default(),
+            slots: vec![],
+            unlocked_slot: None,
+            verified: true,
+            slot_key: None,
+        }
+    }
+
+    /// Recovers the data key by trying `credential` against each slot of the
+    /// matching kind.
+    pub fn unlock(
+        vault_id: String,
+        slots: Vec<KeySlot>,
+        credential: &Credential,
+    ) -> Result<Self, LoadError> {
+        for slot in slots.iter().filter(|s| credential.may_open(s)) {
+            let key = credential.derive(slot)?;
+            let slot_key = SlotKey::new(slot, &key);
+            let Some((data_key, verified)) = open_slot(&vault_id, slot, key)? else {
+                continue;
+            };
+            return Ok(Self {
+                unlocked_slot: Some(slot.id.clone()),
+                vault_id,
+                data_key,
+                slots,
+                verified,
+                slot_key: Some(slot_key),
+            });
+        }
+        if !credential.kind().needs_keyfile() && slots.iter().all(|s| s.kind.needs_keyfile()) {
+            return Err(LoadError::KeyfileRequired);
+        }
+        Err(credential.wrong())
+    }
+
+    pub fn vault_id(&self) -> &str {
+        &self.vault_id
+    }
+
+    pub fn data_key(&self) -> &SecretKey {
+        &self.data_key
+    }
+
+    pub fn verified(&self) -> bool {
+        self.verified
+    }
+
+    pub fn slots(&self) -> &[KeySlot] {
+        &self.slots
+    }
+
+    pub fn slot_key(&self) -> Option<&SlotKey> {
+        self.slot_key.as_ref()
+    }
+
+    pub fn unlocked_slot(&self) -> Option<&KeySlot> {
+        let id = self.unlocked_slot.as_deref()?;
+        self.slots.iter().find(|s| s.id == id)
+    }
+
+    /// Wraps the data key under `credential` in a new slot.
+    pub fn add_slot(
+        &mut self,
+        credential: &Credential,
+        params: KdfParams,
+        label: &str,
+    ) -> anyhow::Result<&KeySlot> {
+        let id = crypto::random_hex(4)?;
+        self.insert_slot(id, label, credential, params)
+    }
+
+    /// Replaces the slot the vault was opened through with one wrapping the
+    /// data key under `credential` and `params`, keeping its id and label.
+    /// The new slot's kind follows `credential`.
+    pub fn rewrap_unlocked_slot(
+        &mut self,
+        credential: &Credential,
+        params: KdfParams,
+    ) -> anyhow::Result<()> {
+        let Some(old) = self.unlocked_slot().cloned() else {
+            anyhow::bail!("vault was not opened through a key slot");
+        };
+        let id = if old.wrapped_key_b64.is_none() {
+            // A legacy slot's key is the data key itself; it can only be
+            // replaced by switching to a fresh data key.
+            self.rotate();
+            crypto::random_hex(4)?
+        } else {
+            self.slots.retain(|s| s.id != old.id);
+            old.id
+        };
+        self.insert_slot(id, &old.label, credential, params)?;
+        Ok(())
+    }
+
+    /// Switches to a new random data key wrapped under `credential` and
+    /// `params` in the slot the vault was opened through, keeping its id and
+    /// label. Every other slot wraps the old key and cannot be re-wrapped
+    /// without its secret, so they are dropped and returned.
+    pub fn rotate_unlocked_slot(
+        &mut self,
+        credential: &Credential,
+        params: KdfParams,
+    ) -> anyhow::Result<Vec<KeySlot>> {
+        let Some(old) = self.unlocked_slot().cloned() else {
+            anyhow::bail!("vault was not opened through a key slot");
+        };
+        let id = if old.wrapped_key_b64.is_none() {
+            crypto::random_hex(4)?
+        } else {
+            old.id.clone()
+        };
+        let mut revoked = self.rotate();
+        revoked.retain(|s| s.id != old.id);
+        self.insert_slot(id, &old.label, credential, params)?;
+        Ok(revoked)
+    }
+
+    /// Whether the vault was opened through a slot written before data keys
+    /// were wrapped.
+    pub fn is_legacy(&self) -> bool {
+        self.unlocked_slot()
+            .is_some_and(|s| s.wrapped_key_b64.is_none())
+    }
+
+    pub fn remove_slot(&mut self, id: &str) -> anyhow::Result<KeySlot> {
+        let pos = self
+            .slots
+            .iter()
+            .position(|s| s.id == id)
+            .ok_or_else(|| anyhow::anyhow!("no key slot with id {id}"))?;
+        if self.slots.len() == 1 {
+            anyhow::bail!("refusing to remove the only key slot");
+        }
+        if self.slots[pos].wrapped_key_b64.is_none() {
+            anyhow::bail!("the legacy slot can only be replaced by rotating the data key (passwd)");
+        }
+        Ok(self.slots.remove(pos))
+    }
+
+    /// Switches to a new random data key. Every existing slot wraps the old
+    /// key, so all of them are dropped and returned; the caller adds at least
+    /// one new slot before saving.
+    pub fn rotate(&mut self) -> Vec<KeySlot> {
+        self.data_key = SecretKey::default();
+        self.unlocked_slot = None;
+        self.verified = true;
+        self.slot_key = None;
+        std::mem::take(&mut self.slots)
+    }
+
+    fn insert_slot(
+        &mut self,
+        id: String,
+        label: &str,
+        credential: &Credential,
+        params: KdfParams,
+    ) -> anyhow::Result<&KeySlot> {
+        let mut slot = KeySlot {
+            id,
+            kind: credential.kind(),
+            label: label.to_owned(),
+            kdf: KDF_ARGON2I.into(),
+            kdf_iterations: params.iterations,
+            kdf_memory_kib: params.memory_kib,
+            salt_b64: general_purpose::STANDARD.encode(Salt::default().as_ref()),
+            wrapped_key_b64: None,
+            key_check_b64: None,
+            created_ms: timestamp::now_millis(),
+        };
+        let key = credential.derive(&slot)?;
+        slot.key_check_b64 = Some(general_purpose::STANDARD.encode(crypto::key_check(&key)?));
+        let wrapped = crypto::seal(
+            &key,
+            self.data_key.unprotected_as_bytes(),
+            Some(&slot.associated_data(&self.vault_id)),
+        )?;
+        slot.wrapped_key_b64 = Some(general_purpose::STANDARD.encode(wrapped));
+        if self.unlocked_slot.is_none() {
+            self.unlocked_slot = Some(slot.id.clone());
+        }
+        if self.unlocked_slot.as_deref() == Some(&slot.id) {
+            self.slot_key = Some(SlotKey::new(&slot, &key));
+        }
+        self.slots.push(slot);
+        Ok(self.slots.last().expect("just pushed"))
+    }
+}
+
+/// Opens one slot with its derived key. `Ok(None)` means the key was wrong.
+fn open_slot(
+    vault_id: &str,
+    slot: &KeySlot,
+    key: SecretKey,
+) -> Result<Option<(SecretKey, bool)>, LoadError> {
+    let decode = |b64: &str| {
+        general_purpose::STANDARD
+            .decode(b64)
+            .map_err(|e| LoadError::Corrupt(format!("key slot {}: {e}", slot.id)))
+    };
+    match (&slot.wrapped_key_b64, &slot.key_check_b64) {
+        (Some(wrapped), check) => {
+            // With a check, a key that passes it is right, so a wrapped key
+            // that then fails to open was modified, or its vault id or slot
+            // fields were.
+            let confirmed = match check {
+                Some(check) if !crypto::verify_key_check(&key, &decode(check)?) => {
+                    return Ok(None);
+                }
+                Some(_) => true,
+                None => false,
+            };
+            let wrapped = decode(wrapped)?;
+            match crypto::open(&key, &wrapped, Some(&slot.associated_data(vault_id))) {
+                Ok(bytes) => {
+                    let data_key = SecretKey::from_slice(&bytes).map_err(|_| {
+                        LoadError::Corrupt(format!("key slot {} holds an invalid key", slot.id))
+                    })?;
+                    Ok(Some((data_key, true)))
+                }
+                Err(_) if confirmed => Err(LoadError::Integrity(format!(
+                    "key slot {} or the vault id was modified",
+                    slot.id
+                ))),
+                Err(_) => Ok(None),
+            }
+        }
+        (None, Some(check)) => {
+            let check = decode(check)?;
+            Ok(crypto::verify_key_check(&key, &check).then_some((key, true)))
+        }
+        // A slot from a version 1 file: nothing to check the key against
+        // until the blob itself is opened.
+        (None, None) => Ok(Some((key, false))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST: KdfParams = KdfParams {
+        iterations: 3,
+        memory_kib: 64,
+    };
+
+    fn password(s: &str) -> Credential {
+        Credential::Password(s.to_owned().into())
+    }
+
+    #[test]
+    fn rotating_replaces_the_data_key_and_revokes_other_slots() {
+        let mut keyring = Keyring::new("vault".into());
+        let id = keyring
+            .add_slot(&password("old"), FAST, "master password")
+            .unwrap()
+            .id
+            .clone();
+        let other = keyring
+            .add_slot(&password("other"), FAST, "spare")
+            .unwrap()
+            .id
+            .clone();
+        let old_key = keyring.data_key().unprotected_as_bytes().to_vec();
+        let old_slots = keyring.slots().to_vec();
+
+        let mut keyring =
+            Keyring::unlock("vault".into(), old_slots.clone(), &password("old")).unwrap();
+        let revoked = keyring
+            .rotate_unlocked_slot(&password("new"), FAST)
+            .unwrap();
+
+        assert_eq!(revoked.iter().map(|s| &s.id).collect::<Vec<_>>(), [&other]);
+        assert_ne!(keyring.data_key().unprotected_as_bytes(), &old_key[..]);
+        assert_eq!(keyring.slots().len(), 1);
+        let slot = keyring.unlocked_slot().unwrap();
+        assert_eq!((&slot.id, slot.label.as_str()), (&id, "master password"));
+
+        let slots = keyring.slots().to_vec();
+        assert!(Keyring::unlock("vault".into(), slots.clone(), &password("old")).is_err());
+        let reopened = Keyring::unlock("vault".into(), slots, &password("new")).unwrap();
+        assert_eq!(
+            reopened.data_key().unprotected_as_bytes(),
+            keyring.data_key().unprotected_as_bytes()
+        );
+        // The old slots still open only the old key.
+        let stale = Keyring::unlock("vault".into(), old_slots, &password("old")).unwrap();
+        assert_eq!(stale.data_key().unprotected_as_bytes(), &old_key[..]);
+    }
+}
@@ -0,0 +1,141 @@
+//! The printable emergency kit: everything needed to find and open a vault
+//! when every other unlock method is lost.
+
+use lockbox::{format::KeySlot, timestamp};
+
+/// Words per line when the recovery key is printed.
+const WORDS_PER_LINE: usize = 6;
+const WARNING: &str =
+    "Anyone holding this sheet can open the vault. Keep it offline and out of sight.";
+
+pub struct Kit<'a> {
+    pub vault_id: &'a str,
+    pub vault_path: &'a str,
+    pub slots: &'a [KeySlot],
+    pub recovery_slot_id: &'a str,
+    pub mnemonic: &'a str,
+    pub created_ms: u64,
+}
+
+impl Kit<'_> {
+    fn mnemonic_lines(&self) -> Vec<String> {
+        let words = self.mnemonic.split(' ').collect::<Vec<_>>();
+        words
+            .chunks(WORDS_PER_LINE)
+            .enumerate()
+            .map(|(i, chunk)| format!("{:>2}. {}", i * WORDS_PER_LINE + 1, chunk.join(" ")))
+            .collect()
+    }
+
+    fn unlock_command(&self) -> String {
+        format!("lockbox {} --recovery-key list", self.vault_path)
+    }
+
+    fn slot_line(slot: &KeySlot) -> String {
+        format!(
+            "{} | {} | {} | {} iterations={}, memory_kib={}",
+            slot.id, slot.kind, slot.label, slot.kdf, slot.kdf_iterations, slot.kdf_memory_kib
+        )
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![
+            "LOCKBOX EMERGENCY KIT".to_owned(),
+            "=====================".to_owned(),
+            String::new(),
+            format!(
+                "Created:    {}",
+                timestamp::format_utc(self.created_ms / 1000)
+            ),
+            format!("Vault id:   {}", self.vault_id),
+            format!("Vault file: {}", self.vault_path),
+            String::new(),
+            "Key slots:".to_owned(),
+        ];
+        lines.extend(
+            self.slots
+                .iter()
+                .map(|s| format!("  {}", Self::slot_line(s))),
+        );
+        lines.push(String::new());
+        lines.push(format!("Recovery key (slot {}):", self.recovery_slot_id));
+        lines.extend(self.mnemonic_lines().iter().map(|l| format!("  {l}")));
+        lines.extend([
+            String::new(),
+            "To unlock with the recovery key, run".to_owned(),
+            format!("  {}", self.unlock_command()),
+            "and enter the words above when asked.".to_owned(),
+            String::new(),
+            WARNING.to_owned(),
+        ]);
+        lines.join("\n") + "\n"
+    }
+
+    pub fn to_html(&self) -> String {
+        let details = [
+            ("Created", timestamp::format_utc(self.created_ms / 1000)),
+            ("Vault id", self.vault_id.to_owned()),
+            ("Vault file", self.vault_path.to_owned()),
+        ]
+        .iter()
+        .map(|(term, value)| format!("<dt>{term}</dt><dd><code>{}</code></dd>\n", escape(value)))
+        .collect::<String>();
+        let slots = self
+            .slots
+            .iter()
+            .map(|s| format!("<li><code>{}</code></li>\n", escape(&Self::slot_line(s))))
+            .collect::<String>();
+        format!(
+            r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8">
+<title>Lockbox emergency kit</title>
+<style>body{{font-family:sans-serif;max-width:40em;margin:2em auto}}pre{{font-size:1.2em}}</style>
+</head><body>
+<h1>Lockbox emergency kit</h1>
+<dl>
+{details}</dl>
+<h2>Key slots</h2>
+<ul>
+{slots}</ul>
+<h2>Recovery key (slot {slot_id})</h2>
+<pre>{mnemonic}</pre>
+<p>To unlock with the recovery key, run <code>{command}</code> and enter the words above when asked.</p>
+<p><strong>{WARNING}</strong></p>
+</body></html>
+"#,
+            slot_id = escape(self.recovery_slot_id),
+            mnemonic = escape(&self.mnemonic_lines().join("\n")),
+            command = escape(&self.unlock_command()),
+        )
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_escapes_the_vault_path() {
+        let kit = Kit {
+            vault_id: "abc",
+            vault_path: "/home/<me>/db.json",
+            slots: &[],
+            recovery_slot_id: "0a1b2c3d",
+            mnemonic: "acorn alarm album amber angle apple armor",
+            created_ms: 0,
+        };
+
+        let html = kit.to_html();
+
+        assert!(html.contains("/home/&lt;me&gt;/db.json"));
+        assert!(!html.contains("<me>"));
+        assert!(html.contains(" 1. acorn alarm album amber angle apple\n 7. armor"));
+    }
+}
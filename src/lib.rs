@@ -0,0 +1,48 @@
+//! Lockbox keeps passwords in an encrypted vault file.
+//!
+//! [`VaultHandle`] is the way in: open or create a vault, unlock it with a
+//! [`Credential`], read and change its entries, and save. The other modules
+//! are the pieces it is built from, public for tools that need more control
+//! over key slots, backups or the file format.
+//!
+//! ```no_run
+//! use lockbox::{Credential, LockMode, VaultHandle};
+//! use std::time::Duration;
+//!
+//! let mut vault = VaultHandle::open("db.json", LockMode::Exclusive, Duration::from_secs(10))?;
+//! vault.unlock(&Credential::Password("master password".to_owned().into()))?;
+//! let id = vault.add_entry("mail".into(), "me".into(), "hunter2".to_owned().into())?;
+//! vault.save()?;
+//! # let _ = id;
+//! # Ok::<(), lockbox::Error>(())
+//! ```
+
+pub mod atomic;
+pub mod backup;
+pub mod crypto;
+pub mod entry;
+pub mod error;
+pub mod format;
+pub mod generate;
+mod handle;
+pub mod history;
+pub mod kdf;
+pub mod keyfile;
+pub mod keyring;
+pub mod lock;
+pub mod recovery;
+pub mod secret;
+mod shamir;
+mod store;
+pub mod strength;
+pub mod timestamp;
+mod words;
+
+pub use entry::EntryData;
+pub use error::{Error, LoadError};
+pub use handle::VaultHandle;
+pub use kdf::KdfParams;
+pub use keyring::Credential;
+pub use lock::LockMode;
+pub use secret::SecretString;
+pub use store::{CustomField, Settings, Store, Vault, decrypt_store, encrypt_store};
@@ -0,0 +1,202 @@
+//! Advisory locking so concurrent lockbox processes don't lose each other's
+//! writes.
+//!
+//! The lock is an `flock` on `<vault>.lock`, held from before the vault is
+//! read until after it is saved. Commands that only read take it shared; the
+//! holder writes its PID into the file so a blocked process can say who it is
+//! waiting for. The lock file itself is never deleted, since removing it
+//! would let two processes lock different inodes under the same name.
+
+use std::{
+    ffi::OsString,
+    fmt,
+    fs::{File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+#[derive(Debug)]
+pub enum LockError {
+    Locked { pid: Option<u32>, waited: Duration },
+    Io(io::Error),
+}
+
+impl LockError {
+    /// `EX_TEMPFAIL`: trying again later may well succeed.
+    pub fn exit_code(&self) -> u8 {
+        75
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            LockError::Locked { .. } => "vault-busy",
+            LockError::Io(_) => "lock-io",
+        }
+    }
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockError::Locked { pid, waited } => {
+                match pid {
+                    Some(pid) => write!(f, "vault is locked by PID {pid}")?,
+                    None => write!(f, "vault is locked by another process")?,
+                }
+                write!(f, " (gave up after {:.1}s)", waited.as_secs_f64())
+            }
+            LockError::Io(e) => write!(f, "unable to lock vault: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for LockError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LockError::Io(e) => Some(e),
+            LockError::Locked { .. } => None,
+        }
+    }
+}
+
+/// A held lock on a vault, released when dropped.
+#[derive(Debug)]
+pub struct VaultLock {
+    _file: File,
+}
+
+impl VaultLock {
+    /// Locks `vault`, retrying until `timeout` has passed.
+    pub fn acquire(vault: &Path, mode: LockMode, timeout: Duration) -> Result<Self, LockError> {
+        let path = lock_path(vault);
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(&path).map_err(LockError::Io)?;
+
+        let start = Instant::now();
+        loop {
+            match try_lock(&file, mode) {
+                Ok(true) => break,
+                Ok(false) if start.elapsed() < timeout => thread::sleep(RETRY_INTERVAL),
+                Ok(false) => {
+                    return Err(LockError::Locked {
+                        pid: read_pid(&path),
+                        waited: start.elapsed(),
+                    });
+                }
+                Err(e) => return Err(LockError::Io(e)),
+            }
+        }
+        write_pid(&file).map_err(LockError::Io)?;
+        Ok(VaultLock { _file: file })
+    }
+}
+
+pub fn lock_path(vault: &Path) -> PathBuf {
+    let mut name = vault
+        .file_name()
+        .map(OsString::from)
+        .unwrap_or_else(|| OsString::from("vault"));
+    name.push(".lock");
+    vault.with_file_name(name)
+}
+
+fn read_pid(path: &Path) -> Option<u32> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Overwrites the PID in place with a fixed-width record so concurrent
+/// shared holders can't interleave into garbage.
+#[cfg(unix)]
+fn write_pid(file: &File) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+
+    let record = format!("{:>10}\n", std::process::id());
+    file.write_all_at(record.as_bytes(), 0)?;
+    file.set_len(record.len() as u64)
+}
+
+#[cfg(unix)]
+fn try_lock(file: &File, mode: LockMode) -> io::Result<bool> {
+    use std::os::fd::AsRawFd;
+
+    let op = match mode {
+        LockMode::Shared => libc::LOCK_SH,
+        LockMode::Exclusive => libc::LOCK_EX,
+    };
+    if unsafe { libc::flock(file.as_raw_fd(), op | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(false),
+        Some(libc::EINTR) => try_lock(file, mode),
+        _ => Err(err),
+    }
+}
+
+#[cfg(not(unix))]
+fn write_pid(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn try_lock(_file: &File, _mode: LockMode) -> io::Result<bool> {
+    Ok(true)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    const NO_WAIT: Duration = Duration::from_millis(0);
+
+    #[test]
+    fn exclusive_lock_reports_holder_pid() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = dir.path().join("db.json");
+
+        let _held = VaultLock::acquire(&vault, LockMode::Exclusive, NO_WAIT).unwrap();
+        let err = VaultLock::acquire(&vault, LockMode::Shared, NO_WAIT).unwrap_err();
+
+        match err {
+            LockError::Locked { pid, .. } => assert_eq!(pid, Some(std::process::id())),
+            other => panic!("unexpected error: {other}"),
+        }
+    }
+
+    #[test]
+    fn shared_locks_coexist_but_block_writers() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = dir.path().join("db.json");
+
+        let _a = VaultLock::acquire(&vault, LockMode::Shared, NO_WAIT).unwrap();
+        let _b = VaultLock::acquire(&vault, LockMode::Shared, NO_WAIT).unwrap();
+        assert!(matches!(
+            VaultLock::acquire(&vault, LockMode::Exclusive, Duration::from_millis(120)),
+            Err(LockError::Locked { .. })
+        ));
+    }
+
+    #[test]
+    fn lock_is_released_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = dir.path().join("db.json");
+
+        drop(VaultLock::acquire(&vault, LockMode::Exclusive, NO_WAIT).unwrap());
+        VaultLock::acquire(&vault, LockMode::Exclusive, NO_WAIT).unwrap();
+        assert!(lock_path(&vault).exists());
+    }
+}
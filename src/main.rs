@@ -1,211 +1,2010 @@
 use anyhow::Context;
-use base64::Engine;
-use base64::engine::general_purpose;
 use clap::{Parser, Subcommand};
-use orion::aead;
-use orion::kdf::{self, Password, Salt};
+use input::{GivenMaster, Prompt};
+use lockbox::{
+    Credential, CustomField, EntryData, Error, KdfParams, LoadError, Store, Vault, VaultHandle,
+    backup,
+    entry::{self, CardExpiry, Date, WifiSecurity},
+    format::{self, Header, KeySlot, SlotKind, VaultDocument},
+    generate, kdf,
+    keyfile::{self, Keyfile},
+    keyring::Keyring,
+    lock::{LockError, LockMode, VaultLock},
+    recovery::{self, RecoveryShare, ShareEncoding},
+    secret::{self, SecretString},
+    strength, timestamp,
+};
+use output::{Format, Printer};
 use rpassword::prompt_password;
-use serde::{Deserialize, Serialize};
 use std::{
-    fs::{self, File},
-    io::{BufReader, Write},
-    path::PathBuf,
+    fs,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
 };
-use zeroize::Zeroize;
+use zeroize::Zeroizing;
+
+mod agent;
+mod edit;
+mod input;
+mod kit;
+mod migrate;
+mod output;
+mod pinentry;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "locbox",
+    version,
+    about = "Lightweight CLI password manager.",
+    after_long_help = MASTER_PASSWORD_HELP
+)]
+struct Cli {
+    db: Option<PathBuf>,
+
+    /// How long to wait for another lockbox process to release the vault.
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = 10)]
+    lock_timeout: u64,
+
+    /// Keyfile to unlock with, alone or together with the master password.
+    #[arg(long, global = true, value_name = "PATH")]
+    keyfile: Option<PathBuf>,
+
+    /// Unlock with the recovery key from an emergency kit.
+    #[arg(long, global = true, conflicts_with = "keyfile")]
+    recovery_key: bool,
+
+    /// Read the master password from a file.
+    #[arg(long, global = true, value_name = "PATH")]
+    master_password_file: Option<PathBuf>,
+
+    /// Read the master password from an inherited file descriptor.
+    #[arg(
+        long,
+        global = true,
+        value_name = "FD",
+        conflicts_with = "master_password_file"
+    )]
+    master_password_fd: Option<i32>,
+
+    /// Ask for passwords through this pinentry program instead of the terminal.
+    #[arg(long, global = true, value_name = "PROGRAM")]
+    pinentry: Option<PathBuf>,
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Vault {
-    id: usize,
-    service: String,
-    username: String,
-    password: String,
+    /// Neither use a running `lockbox agent` nor give it keys.
+    #[arg(long, global = true)]
+    no_agent: bool,
+
+    /// How to print results and errors; see OUTPUT FORMATS in `lockbox --help`.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    format: Format,
+
+    #[command(subcommand)]
+    command: Commands,
 }
 
-impl Vault {
-    fn new(id: usize, service: String, username: String, password: String) -> Self {
-        Self {
-            id,
-            service,
-            username,
-            password,
-        }
-    }
+const MASTER_PASSWORD_HELP: &str = "\
+The master password is taken from the first of these that is set:
+  1. --master-password-file or --master-password-fd
+  2. the LOCKBOX_MASTER_PASSWORD_CMD environment variable, a command run with `sh -c` that prints it
+  3. --pinentry
+  4. a prompt on the terminal
+New passwords and recovery keys are always asked for through the last two, except that \
+`init` and `migrate-plaintext` take the new vault's password from the first two.
+A running `lockbox agent` that holds the vault's key is used before any of these; see `--no-agent`.
+
+OUTPUT FORMATS
+`--format table` prints text for people. `json` prints one JSON value per command: an array \
+of objects for `list`, `backup list`, `slot list` and `recovery split`, an object otherwise. \
+`jsonl` prints one object per line, and `tsv` a header line of field names and then one \
+tab-separated line per object, with backslash, tab, newline and carriage return escaped \
+as in C, list items joined with commas and their own commas escaped, and objects as JSON. Objects have these fields, in this order:
+  list                 id, service, username, password, urls, notes, tags, fields, created,
+                       modified, last_used, type, details
+  init                 path, vault_id
+  passwd               slot_id, revoked_slots
+  add                  id, generated_length, type
+  remove               id, removed
+  migrate-plaintext    path, entries
+  backup list          id, taken, entries
+  backup restore       id, taken, entries
+  backup policy        keep_last, daily, weekly
+  kdf show             iterations, memory_kib, min_iterations, min_memory_kib
+  kdf set              iterations, memory_kib
+  kdf calibrate        iterations, memory_kib, millis, applied
+  slot list            unlocked, id, kind, label, created, kdf_iterations, kdf_memory_kib
+  slot add, remove     id, kind, removed
+  recovery split       number, shares, threshold, slot_id, share
+  recovery combine     slot_id, revoked_slots
+  emergency-kit        vault_id, slot_id, recovery_key, path, revoked_slots
+  keyfile generate     path
+  lock                 locked
+  get                  as for list, secrets masked as ******** unless --reveal; --field
+                       prints a bare value in any format
+  edit                 id, changed
+  history              id, number, replaced, type, secret
+  history restore      id, number
+  history policy       keep_last, max_age_days
+Absent values are null, or empty in tsv. Later releases may add fields but never remove them.
+Errors go to standard error: an object with an `error` object holding code, exit_code and \
+message in json and jsonl, a header and a row of code, exit_code and message in tsv.";
+
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// Create a new, empty vault.
+    Init {
+        #[command(flatten)]
+        kdf: KdfArgs,
+        /// Unlock with the `--keyfile` alone, without a master password.
+        #[arg(long, requires = "keyfile")]
+        no_password: bool,
+    },
+    /// Change the password of the key slot the vault is unlocked with. The
+    /// data key is replaced too, which revokes every other key slot, recovery
+    /// shares and emergency kits included; if there are any, you are asked
+    /// first unless --revoke-recovery or --keep-recovery says what to do.
+    Passwd {
+        /// Revoke the other key slots without asking.
+        #[arg(long)]
+        revoke_recovery: bool,
+        /// Keep the data key and with it the other key slots. Copies of the
+        /// vault from before then still open with the old password.
+        #[arg(long, conflicts_with = "revoke_recovery")]
+        keep_recovery: bool,
+    },
+    /// Add a login, or an entry of another type through its subcommand. A
+    /// login's password is prompted for unless another source is given.
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    Add {
+        #[command(subcommand)]
+        kind: Option<Box<AddCommand>>,
+        #[command(flatten)]
+        login: LoginArgs,
+        #[command(flatten)]
+        details: EntryArgs,
+    },
+    Remove {
+        id: usize,
+    },
+    List,
+    /// Encrypt a vault left unencrypted by an early release, destroying the plaintext.
+    MigratePlaintext,
+    /// Manage the copies of the vault kept before every save.
+    Backup {
+        #[command(subcommand)]
+        command: BackupCommand,
+    },
+    /// Inspect or tune the Argon2 key-derivation parameters.
+    Kdf {
+        #[command(subcommand)]
+        command: KdfCommand,
+    },
+    /// Manage the key slots that can unlock the vault.
+    Slot {
+        #[command(subcommand)]
+        command: SlotCommand,
+    },
+    /// Split a recovery key among several people, or recover with their shares.
+    Recovery {
+        #[command(subcommand)]
+        command: RecoveryCommand,
+    },
+    /// Print a sheet with a new recovery key to keep offline; it replaces any earlier kit's key.
+    EmergencyKit {
+        /// Produce HTML rather than plain text.
+        #[arg(long)]
+        html: bool,
+        /// Write the kit to a new file instead of standard output.
+        #[arg(long, short, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Create keyfiles.
+    Keyfile {
+        #[command(subcommand)]
+        command: KeyfileCommand,
+    },
+    /// Keep the keys of unlocked vaults in memory and hand them to other
+    /// lockbox commands, so they need neither a password nor Argon2. Runs in
+    /// the foreground on the socket named by LOCKBOX_AGENT_SOCK, or a default
+    /// one.
+    Agent {
+        /// Forget every key once none has been used for this long.
+        #[arg(long, value_name = "SECONDS", default_value_t = agent::DEFAULT_IDLE_TIMEOUT.as_secs())]
+        idle_timeout: u64,
+    },
+    /// Make a running agent forget every key it holds.
+    Lock,
+    /// Show one entry, found by id or name, with its secrets masked. Revealing
+    /// a secret records the time as the entry's last use.
+    #[command(visible_alias = "show")]
+    Get {
+        /// The entry's id or name.
+        entry: String,
+        /// Show secrets instead of masking them.
+        #[arg(long)]
+        reveal: bool,
+        /// Print only this field's raw value, such as `password`, `username`,
+        /// `number` or a custom field's name, whatever `--format` says.
+        #[arg(long, value_name = "NAME")]
+        field: Option<String>,
+    },
+    /// Change an entry, found by id or name, keeping its id. Give the new
+    /// values as flags, or answer questions with --interactive, or change it
+    /// in $VISUAL or $EDITOR with --editor.
+    Edit {
+        /// The entry's id or name.
+        entry: String,
+        /// Ask about each value in turn.
+        #[arg(long, short, conflicts_with_all = ["editor", "EditArgs", "EntryArgs", "PasswordArgs"])]
+        interactive: bool,
+        /// Edit the entry as TOML, kept in a file on a tmpfs while the editor runs.
+        /// The vault stays usable meanwhile; if the entry changes, the edit is
+        /// not saved.
+        #[arg(long, conflicts_with_all = ["EditArgs", "EntryArgs", "PasswordArgs"])]
+        editor: bool,
+        #[command(flatten)]
+        changes: Box<EditArgs>,
+        #[command(flatten)]
+        source: PasswordArgs,
+        #[command(flatten)]
+        details: EntryArgs,
+    },
+    /// Show an entry's earlier values, newest first, with secrets masked.
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    History {
+        #[command(subcommand)]
+        command: Option<HistoryCommand>,
+        #[arg(required = true)]
+        id: Option<usize>,
+    },
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
-struct Store {
-    next_id: usize,
-    vault_items: Vec<Vault>,
+#[derive(Debug, Subcommand)]
+enum HistoryCommand {
+    /// Bring back an earlier value, numbered as `history` lists them; the
+    /// current value joins the history.
+    Restore { id: usize, number: usize },
+    /// Show or change how many earlier values each entry keeps.
+    Policy {
+        /// Keep this many per entry; 0 stops keeping them.
+        #[arg(long)]
+        keep_last: Option<usize>,
+        /// Forget values replaced more than this many days ago; 0 never does.
+        #[arg(long)]
+        max_age_days: Option<u64>,
+    },
 }
 
-impl Store {
-    fn load(path: &PathBuf, master: &str) -> Self {
-        if !path.exists() {
-            return Store {
-                next_id: 1,
-                vault_items: vec![],
-            };
-        }
-        let file = match File::open(path) {
-            Ok(f) => f,
-            Err(_) => {
-                return Store {
-                    next_id: 1,
-                    vault_items: vec![],
+#[derive(Debug, Subcommand)]
+enum AddCommand {
+    /// A username and password; the same as `add SERVICE USERNAME`.
+    Login {
+        #[command(flatten)]
+        login: LoginArgs,
+        #[command(flatten)]
+        details: EntryArgs,
+    },
+    /// Secret text, read from standard input unless a file is given.
+    Note {
+        name: String,
+        #[arg(long, value_name = "PATH")]
+        text_file: Option<PathBuf>,
+        #[command(flatten)]
+        details: EntryArgs,
+    },
+    /// A payment card. The number and security code are prompted for.
+    Card {
+        name: String,
+        /// The name on the card.
+        #[arg(long)]
+        holder: String,
+        #[arg(long, value_name = "MM/YY")]
+        expiry: CardExpiry,
+        #[command(flatten)]
+        details: EntryArgs,
+    },
+    /// An SSH key pair, read from its files.
+    SshKey {
+        name: String,
+        #[arg(long, value_name = "PATH")]
+        private_key: PathBuf,
+        /// Defaults to the private key's path with `.pub` added, if that exists.
+        #[arg(long, value_name = "PATH")]
+        public_key: Option<PathBuf>,
+        /// Prompt for the passphrase protecting the private key, to keep with it.
+        #[arg(long)]
+        passphrase: bool,
+        #[command(flatten)]
+        details: EntryArgs,
+    },
+    /// An API token, prompted for unless read from standard input.
+    ApiToken {
+        name: String,
+        /// A scope the token grants; may be repeated.
+        #[arg(long = "scope", value_name = "SCOPE")]
+        scopes: Vec<String>,
+        #[arg(long, value_name = "YYYY-MM-DD")]
+        expires: Option<Date>,
+        #[arg(long)]
+        token_stdin: bool,
+        #[command(flatten)]
+        details: EntryArgs,
+    },
+    /// A Wi-Fi network. The password is prompted for unless it is open.
+    Wifi {
+        ssid: String,
+        #[arg(long, value_enum, default_value_t)]
+        security: WifiSecurity,
+        /// The name to list it under instead of the SSID.
+        #[arg(long)]
+        name: Option<String>,
+        #[command(flatten)]
+        details: EntryArgs,
+    },
+}
+
+impl AddCommand {
+    /// Gathers the entry's name and values, prompting or reading files as
+    /// needed, and the length of any password generated for it.
+    fn read(self) -> anyhow::Result<(String, EntryData, EntryArgs, Option<usize>)> {
+        Ok(match self {
+            AddCommand::Login { login, details } => {
+                let (service, data, generated) = login.read()?;
+                (service, data, details, generated)
+            }
+            AddCommand::Note {
+                name,
+                text_file,
+                details,
+            } => {
+                let text = match text_file {
+                    Some(path) => input::secret_from_file(&path)?,
+                    None => {
+                        if std::io::stdin().is_terminal() {
+                            eprintln!("Type the note, then press Ctrl-D:");
+                        }
+                        input::secret_from_stdin()?
+                    }
                 };
+                (name, EntryData::SecureNote { text }, details, None)
             }
-        };
-        let reader = BufReader::new(file);
-        match serde_json::from_reader::<_, EncryptedFile>(reader) {
-            Ok(enc) => decrypt_store(&enc, master).unwrap_or_else(|e| {
-                eprintln!("{e}");
-                Store {
-                    next_id: 1,
-                    vault_items: vec![],
-                }
-            }),
-            Err(_) => {
-                let file = match File::open(path) {
-                    Ok(f) => f,
-                    Err(_) => {
-                        return Store {
-                            next_id: 1,
-                            vault_items: vec![],
-                        };
+            AddCommand::Card {
+                name,
+                holder,
+                expiry,
+                details,
+            } => {
+                let number = entry::normalize_card_number(&prompt_password("Card number: ")?);
+                let cvv = SecretString::new(prompt_password("Security code (empty for none): ")?);
+                let data = EntryData::Card {
+                    holder,
+                    number,
+                    expiry,
+                    cvv: (!cvv.is_empty()).then_some(cvv),
+                };
+                (name, data, details, None)
+            }
+            AddCommand::SshKey {
+                name,
+                private_key,
+                public_key,
+                passphrase,
+                details,
+            } => {
+                let public_path = public_key.or_else(|| {
+                    let mut path = private_key.clone().into_os_string();
+                    path.push(".pub");
+                    Some(PathBuf::from(path)).filter(|p| p.exists())
+                });
+                let public_key = public_path
+                    .map(|path| {
+                        fs::read_to_string(&path)
+                            .map(|key| key.trim().to_owned())
+                            .with_context(|| format!("unable to read {}", path.display()))
+                    })
+                    .transpose()?;
+                let data = EntryData::SshKey {
+                    private_key: input::secret_from_file(&private_key)?,
+                    public_key,
+                    passphrase: passphrase
+                        .then(|| prompt_password("Key passphrase: ").map(SecretString::new))
+                        .transpose()?,
+                };
+                (name, data, details, None)
+            }
+            AddCommand::ApiToken {
+                name,
+                scopes,
+                expires,
+                token_stdin,
+                details,
+            } => {
+                let token = if token_stdin {
+                    input::secret_from_stdin()?
+                } else {
+                    SecretString::new(prompt_password(format!("Token for {name}: "))?)
+                };
+                let token = SecretString::new(token.trim().to_owned());
+                let data = EntryData::ApiToken {
+                    token,
+                    scopes,
+                    expires,
+                };
+                (name, data, details, None)
+            }
+            AddCommand::Wifi {
+                ssid,
+                security,
+                name,
+                details,
+            } => {
+                let password = if security == WifiSecurity::Open {
+                    None
+                } else {
+                    let password =
+                        SecretString::new(prompt_password(format!("Password for {ssid}: "))?);
+                    let confirm = SecretString::new(prompt_password("Confirm password: ")?);
+                    if password != confirm {
+                        anyhow::bail!("passwords do not match");
                     }
+                    Some(password)
                 };
-                let reader = BufReader::new(file);
-                serde_json::from_reader(reader).unwrap_or(Store {
-                    next_id: 1,
-                    vault_items: vec![],
-                })
+                let data = EntryData::Wifi {
+                    ssid: ssid.clone(),
+                    security,
+                    password,
+                };
+                (name.unwrap_or(ssid), data, details, None)
             }
-        }
+        })
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct LoginArgs {
+    // Optional only to the parser, which requires them unless `add` is
+    // given a subcommand.
+    #[arg(required = true)]
+    service: Option<String>,
+    #[arg(required = true)]
+    username: Option<String>,
+    /// The password itself; visible in shell history and `ps`, so only
+    /// accepted with --insecure-password-arg.
+    #[arg(requires = "insecure_password_arg")]
+    password: Option<String>,
+    #[command(flatten)]
+    source: PasswordArgs,
+}
+
+impl LoginArgs {
+    fn read(self) -> anyhow::Result<(String, EntryData, Option<usize>)> {
+        let (Some(service), Some(username)) = (self.service, self.username) else {
+            anyhow::bail!("give a SERVICE and USERNAME, or the type of entry");
+        };
+        let password = self.source.read(&service, self.password)?;
+        let generated = self.source.generate.then_some(self.source.length);
+        let data = EntryData::Login { username, password };
+        Ok((service, data, generated))
+    }
+}
+
+/// The changes `edit` can make without asking. Each type's own values can
+/// only be changed on entries of that type.
+#[derive(Debug, clap::Args)]
+struct EditArgs {
+    /// A new name to list the entry under.
+    #[arg(long)]
+    name: Option<String>,
+    /// A login's new username.
+    #[arg(long)]
+    username: Option<String>,
+    /// Prompt for a new password for a login or Wi-Fi network, or a new
+    /// token for an API token; the other password sources below work too.
+    #[arg(long, group = "password_source")]
+    password: bool,
+    /// The new password itself; visible in shell history and `ps`, so only
+    /// accepted with --insecure-password-arg.
+    #[arg(long, value_name = "PASSWORD", requires = "insecure_password_arg")]
+    new_password: Option<String>,
+    /// A card's new holder.
+    #[arg(long)]
+    holder: Option<String>,
+    #[arg(long, value_name = "MM/YY")]
+    expiry: Option<CardExpiry>,
+    /// Prompt for a card's new number.
+    #[arg(long)]
+    number: bool,
+    /// Prompt for a card's new security code; an empty one removes it.
+    #[arg(long)]
+    cvv: bool,
+    /// Replace a secure note's text with this file's.
+    #[arg(long, value_name = "PATH")]
+    text_file: Option<PathBuf>,
+    /// Replace an SSH key's private key with this file's.
+    #[arg(long, value_name = "PATH")]
+    private_key: Option<PathBuf>,
+    /// Replace an SSH key's public key with this file's.
+    #[arg(long, value_name = "PATH")]
+    public_key: Option<PathBuf>,
+    /// Prompt for an SSH key's new passphrase; an empty one removes it.
+    #[arg(long)]
+    passphrase: bool,
+    /// Add a scope to an API token; may be repeated.
+    #[arg(long = "scope", value_name = "SCOPE")]
+    scopes: Vec<String>,
+    /// Remove a scope from an API token; may be repeated.
+    #[arg(long = "remove-scope", value_name = "SCOPE")]
+    remove_scopes: Vec<String>,
+    /// An API token's new expiry date.
+    #[arg(long, value_name = "YYYY-MM-DD")]
+    expires: Option<Date>,
+    /// Make an API token never expire.
+    #[arg(long, conflicts_with = "expires")]
+    no_expiry: bool,
+    /// A Wi-Fi network's new SSID.
+    #[arg(long)]
+    ssid: Option<String>,
+    /// A Wi-Fi network's new security; `open` drops its password.
+    #[arg(long, value_enum)]
+    security: Option<WifiSecurity>,
+    /// Remove a login page; may be repeated.
+    #[arg(long = "remove-url", value_name = "URL")]
+    remove_urls: Vec<String>,
+    /// Remove a tag; may be repeated.
+    #[arg(long = "remove-tag", value_name = "TAG")]
+    remove_tags: Vec<String>,
+    /// Remove a custom field; may be repeated.
+    #[arg(long = "remove-field", value_name = "NAME")]
+    remove_fields: Vec<String>,
+    /// Remove the notes.
+    #[arg(long, conflicts_with = "notes")]
+    clear_notes: bool,
+}
+
+// The password sources and details are flattened beside `EditArgs` rather
+// than into it, as clap only groups the arguments of structs that flatten
+// nothing, and `--interactive` and `--editor` conflict with the group.
+impl EditArgs {
+    fn new_secret(&self, source: &PasswordArgs) -> bool {
+        self.password
+            || self.new_password.is_some()
+            || source.password_stdin
+            || source.password_file.is_some()
+            || source.generate
+    }
+
+    /// Each change asked for, and the types of entry it applies to; none for
+    /// every type.
+    fn changes(
+        &self,
+        source: &PasswordArgs,
+        d: &EntryArgs,
+    ) -> [(&'static str, bool, &'static [&'static str]); 17] {
+        [
+            ("--name", self.name.is_some(), &[]),
+            ("--username", self.username.is_some(), &["login"]),
+            (
+                "a new password",
+                self.new_secret(source),
+                &["login", "wifi", "api-token"],
+            ),
+            (
+                "--holder, --expiry, --number and --cvv",
+                self.holder.is_some() || self.expiry.is_some() || self.number || self.cvv,
+                &["card"],
+            ),
+            ("--text-file", self.text_file.is_some(), &["secure-note"]),
+            (
+                "--private-key, --public-key and --passphrase",
+                self.private_key.is_some() || self.public_key.is_some() || self.passphrase,
+                &["ssh-key"],
+            ),
+            (
+                "--scope and --remove-scope",
+                !self.scopes.is_empty() || !self.remove_scopes.is_empty(),
+                &["api-token"],
+            ),
+            (
+                "--expires and --no-expiry",
+                self.expires.is_some() || self.no_expiry,
+                &["api-token"],
+            ),
+            (
+                "--ssid and --security",
+                self.ssid.is_some() || self.security.is_some(),
+                &["wifi"],
+            ),
+            ("--url", !d.urls.is_empty(), &[]),
+            ("--notes", d.notes.is_some() || self.clear_notes, &[]),
+            ("--tag", !d.tags.is_empty(), &[]),
+            ("--field", !d.fields.is_empty(), &[]),
+            ("--secret-field", !d.secret_fields.is_empty(), &[]),
+            ("--remove-url", !self.remove_urls.is_empty(), &[]),
+            ("--remove-tag", !self.remove_tags.is_empty(), &[]),
+            ("--remove-field", !self.remove_fields.is_empty(), &[]),
+        ]
     }
 
-    fn save(&self, path: &PathBuf, master: &str) -> std::io::Result<()> {
-        let enc = encrypt_store(self, master)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        let json = serde_json::to_vec_pretty(&enc).expect("serialize_error");
+    fn is_empty(&self, source: &PasswordArgs, details: &EntryArgs) -> bool {
+        self.changes(source, details)
+            .iter()
+            .all(|(_, given, _)| !given)
+    }
 
-        let tmp = path.with_extension("json.tmp");
+    /// Returns `entry` with the changes made, reading files and prompting for
+    /// new secrets as needed.
+    fn apply(
+        &self,
+        entry: &Vault,
+        source: &PasswordArgs,
+        details: &EntryArgs,
+    ) -> anyhow::Result<Vault> {
+        let tag = entry.data.tag();
+        if let Some((flag, ..)) = self
+            .changes(source, details)
+            .into_iter()
+            .find(|(_, given, types)| *given && !types.is_empty() && !types.contains(&tag))
         {
-            let mut f = File::create(&tmp)?;
-            f.write_all(&json)?;
-            f.flush()?;
+            anyhow::bail!(
+                "entry {} is a {}; {flag} do not apply to it",
+                entry.id,
+                entry.data.type_name()
+            );
+        }
+        let id = entry.id;
+        let mut e = entry.clone();
+        if let Some(name) = &self.name {
+            e.service = name.clone();
+        }
+        let secret = if self.new_secret(source) {
+            Some(source.read(&e.service, self.new_password.clone())?)
+        } else {
+            None
+        };
+        match &mut e.data {
+            EntryData::Login { username, password } => {
+                if let Some(new) = &self.username {
+                    *username = new.clone();
+                }
+                if let Some(new) = secret {
+                    *password = new;
+                }
+            }
+            EntryData::SecureNote { text } => {
+                if let Some(path) = &self.text_file {
+                    *text = input::secret_from_file(path)?;
+                }
+            }
+            EntryData::Card {
+                holder,
+                number,
+                expiry,
+                cvv,
+            } => {
+                if let Some(new) = &self.holder {
+                    *holder = new.clone();
+                }
+                if let Some(new) = self.expiry {
+                    *expiry = new;
+                }
+                if self.number {
+                    *number = entry::normalize_card_number(&prompt_password("Card number: ")?);
+                }
+                if self.cvv {
+                    let new =
+                        SecretString::new(prompt_password("Security code (empty for none): ")?);
+                    *cvv = (!new.is_empty()).then_some(new);
+                }
+            }
+            EntryData::SshKey {
+                private_key,
+                public_key,
+                passphrase,
+            } => {
+                if let Some(path) = &self.private_key {
+                    *private_key = input::secret_from_file(path)?;
+                }
+                if let Some(path) = &self.public_key {
+                    let key = fs::read_to_string(path)
+                        .with_context(|| format!("unable to read {}", path.display()))?;
+                    *public_key = Some(key.trim().to_owned());
+                }
+                if self.passphrase {
+                    let new =
+                        SecretString::new(prompt_password("Key passphrase (empty for none): ")?);
+                    *passphrase = (!new.is_empty()).then_some(new);
+                }
+            }
+            EntryData::ApiToken {
+                token,
+                scopes,
+                expires,
+            } => {
+                if let Some(new) = secret {
+                    *token = SecretString::new(new.trim().to_owned());
+                }
+                for scope in &self.remove_scopes {
+                    let before = scopes.len();
+                    scopes.retain(|s| s != scope);
+                    if scopes.len() == before {
+                        anyhow::bail!("entry {id} has no scope \"{scope}\"");
+                    }
+                }
+                for scope in &self.scopes {
+                    if !scopes.contains(scope) {
+                        scopes.push(scope.clone());
+                    }
+                }
+                if self.expires.is_some() || self.no_expiry {
+                    *expires = self.expires;
+                }
+            }
+            EntryData::Wifi {
+                ssid,
+                security,
+                password,
+            } => {
+                if let Some(new) = &self.ssid {
+                    *ssid = new.clone();
+                }
+                if let Some(new) = self.security {
+                    *security = new;
+                }
+                if *security == WifiSecurity::Open {
+                    *password = None;
+                } else if let Some(new) = secret {
+                    *password = Some(new);
+                }
+            }
+        }
+        for url in &self.remove_urls {
+            let before = e.urls.len();
+            e.urls.retain(|u| u != url);
+            if e.urls.len() == before {
+                anyhow::bail!("entry {id} has no URL {url}");
+            }
+        }
+        for tag in &self.remove_tags {
+            let before = e.tags.len();
+            e.tags.retain(|t| t != tag);
+            if e.tags.len() == before {
+                anyhow::bail!("entry {id} has no tag \"{tag}\"");
+            }
+        }
+        for name in &self.remove_fields {
+            let before = e.fields.len();
+            e.fields.retain(|f| &f.name != name);
+            if e.fields.len() == before {
+                anyhow::bail!("entry {id} has no field \"{name}\"");
+            }
+        }
+        if self.clear_notes {
+            e.notes = None;
+        }
+        details.apply(&mut e, details.custom_fields()?);
+        Ok(e)
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum RecoveryCommand {
+    /// Add a key slot opened by recovery shares, and print the shares.
+    Split {
+        /// How many shares it takes to unlock.
+        #[arg(long)]
+        threshold: u8,
+        /// How many shares to make.
+        #[arg(long)]
+        shares: u8,
+        #[arg(long, value_enum, default_value_t)]
+        encoding: ShareEncoding,
+    },
+    /// Unlock with recovery shares and set a new master password.
+    Combine,
+}
+
+#[derive(Debug, Subcommand)]
+enum KeyfileCommand {
+    /// Write a new keyfile of random bytes; an existing file is never overwritten.
+    Generate { path: PathBuf },
+}
+
+#[derive(Debug, Subcommand)]
+enum SlotCommand {
+    /// List key slots; the one used to unlock is marked with `*`.
+    List,
+    /// Add another password or keyfile that unlocks the vault.
+    Add {
+        #[arg(long, default_value = "")]
+        label: String,
+        /// Require this keyfile for the new slot.
+        #[arg(long, value_name = "PATH")]
+        with_keyfile: Option<PathBuf>,
+        /// Unlock the new slot with its keyfile alone.
+        #[arg(long, requires = "with_keyfile")]
+        no_password: bool,
+    },
+    /// Revoke a key slot. The last remaining slot cannot be removed.
+    Remove { id: String },
+}
+
+#[derive(Debug, Subcommand)]
+enum KdfCommand {
+    /// Show the unlocking slot's KDF parameters and the configured minimum.
+    Show,
+    /// Change the unlocking slot's KDF parameters and re-wrap the data key.
+    Set {
+        #[command(flatten)]
+        kdf: KdfArgs,
+    },
+    /// Benchmark this machine and pick parameters for a target unlock time.
+    Calibrate {
+        /// Desired time to unlock the vault, in milliseconds.
+        #[arg(long, default_value_t = 1000)]
+        target_ms: u64,
+        /// Memory to start from; halved only if the minimum iterations are already too slow.
+        #[arg(long, default_value_t = KdfParams::default().memory_kib)]
+        memory_kib: u32,
+        /// Re-wrap the unlocking slot with the calibrated parameters.
+        #[arg(long)]
+        apply: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum BackupCommand {
+    /// List backups with the time they were taken and their entry count.
+    List,
+    /// Replace the vault with a backup; the current file is backed up first.
+    Restore { id: String },
+    /// Show or change how many backups are kept.
+    Policy {
+        /// Always keep this many of the most recent backups.
+        #[arg(long)]
+        keep_last: Option<usize>,
+        /// Also keep the newest backup from each of this many days.
+        #[arg(long)]
+        daily: Option<usize>,
+        /// Also keep the newest backup from each of this many weeks.
+        #[arg(long)]
+        weekly: Option<usize>,
+    },
+}
+
+#[derive(Debug, clap::Args)]
+struct PasswordArgs {
+    /// Read the password from standard input.
+    #[arg(long, group = "password_source")]
+    password_stdin: bool,
+    /// Read the password from a file.
+    #[arg(long, value_name = "PATH", group = "password_source")]
+    password_file: Option<PathBuf>,
+    /// Generate a random password.
+    #[arg(long, group = "password_source")]
+    generate: bool,
+    /// Length of a generated password.
+    #[arg(long, requires = "generate", default_value_t = generate::DEFAULT_LENGTH)]
+    length: usize,
+    /// Take the password from the command line.
+    #[arg(long, group = "password_source")]
+    insecure_password_arg: bool,
+}
+
+impl PasswordArgs {
+    /// Gets the entry password from the chosen source, or from a hidden,
+    /// confirmed prompt.
+    fn read(&self, service: &str, arg: Option<String>) -> anyhow::Result<SecretString> {
+        if let Some(password) = arg {
+            return Ok(password.into());
+        }
+        if self.password_stdin {
+            return input::secret_from_stdin();
+        }
+        if let Some(path) = &self.password_file {
+            return input::secret_from_file(path);
+        }
+        if self.generate {
+            return generate::password(self.length);
+        }
+        let password = SecretString::new(prompt_password(format!("Password for {service}: "))?);
+        let confirm = SecretString::new(prompt_password("Confirm password: ")?);
+        if password != confirm {
+            anyhow::bail!("passwords do not match");
+        }
+        if password.is_empty() {
+            anyhow::bail!("password is empty");
+        }
+        Ok(password)
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct EntryArgs {
+    /// A login page for the entry; may be repeated.
+    #[arg(long = "url", value_name = "URL")]
+    urls: Vec<String>,
+    /// Free-form notes.
+    #[arg(long)]
+    notes: Option<String>,
+    /// A tag; may be repeated.
+    #[arg(long = "tag", value_name = "TAG", value_parser = parse_tag)]
+    tags: Vec<String>,
+    /// A custom field shown in the clear; may be repeated.
+    #[arg(long = "field", value_name = "NAME=VALUE", value_parser = parse_field)]
+    fields: Vec<(String, String)>,
+    /// A custom field kept hidden like the password, whose value is prompted
+    /// for; may be repeated.
+    #[arg(long = "secret-field", value_name = "NAME", value_parser = parse_field_name)]
+    secret_fields: Vec<String>,
+}
+
+impl EntryArgs {
+    /// The custom fields given, prompting for the value of each secret one.
+    fn custom_fields(&self) -> anyhow::Result<Vec<CustomField>> {
+        let mut fields = self
+            .fields
+            .iter()
+            .map(|(name, value)| CustomField {
+                name: name.clone(),
+                value: value.clone().into(),
+                secret: false,
+            })
+            .collect::<Vec<_>>();
+        for name in &self.secret_fields {
+            let value = SecretString::new(prompt_password(format!("{name}: "))?);
+            fields.push(CustomField {
+                name: name.clone(),
+                value,
+                secret: true,
+            });
+        }
+        Ok(fields)
+    }
+
+    /// Sets the details given on `entry`; a field replaces one of the same
+    /// name.
+    fn apply(&self, entry: &mut Vault, fields: Vec<CustomField>) {
+        for url in &self.urls {
+            if !entry.urls.contains(url) {
+                entry.urls.push(url.clone());
+            }
+        }
+        if let Some(notes) = &self.notes {
+            entry.notes = Some(notes.clone().into());
+        }
+        for tag in &self.tags {
+            entry.add_tag(tag);
+        }
+        for field in fields {
+            match entry.fields.iter_mut().find(|f| f.name == field.name) {
+                Some(existing) => *existing = field,
+                None => entry.fields.push(field),
+            }
         }
-        fs::rename(tmp, path)?;
-        Ok(())
     }
 }
 
-#[derive(Serialize, Deserialize)]
-struct EncryptedFile {
-    salt_b64: String,
-    kdf_iterations: u32,
-    kdf_memory_kib: u32,
-    blob_b64: String,
+fn parse_field(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| "expected NAME=VALUE".to_owned())?;
+    Ok((parse_field_name(name)?, value.to_owned()))
 }
 
-fn encrypt_store(store: &Store, master: &str) -> anyhow::Result<EncryptedFile> {
-    let salt = Salt::default();
-    let iters = 3;
-    let memory_kib = 1 << 16;
+fn parse_field_name(s: &str) -> Result<String, String> {
+    let name = s.trim();
+    if name.is_empty() {
+        return Err("field name is empty".to_owned());
+    }
+    Ok(name.to_owned())
+}
 
-    let password = Password::from_slice(master.as_bytes())?;
-    let dk = kdf::derive_key(&password, &salt, iters, memory_kib, 32)?;
-    let key = orion::aead::SecretKey::from_slice(dk.unprotected_as_bytes())?;
+fn parse_tag(s: &str) -> Result<String, String> {
+    let tag = s.trim();
+    if tag.is_empty() {
+        return Err("tag is empty".to_owned());
+    }
+    Ok(tag.to_owned())
+}
 
-    let plaintext = serde_json::to_vec(store).context("serialize_store")?;
+#[derive(Debug, clap::Args)]
+struct KdfArgs {
+    /// Argon2i iterations.
+    #[arg(long = "kdf-iterations")]
+    iterations: Option<u32>,
+    /// Argon2i memory cost in KiB.
+    #[arg(long = "kdf-memory-kib")]
+    memory_kib: Option<u32>,
+}
 
-    let blob = aead::seal(&key, &plaintext).context("encryption_failed")?;
+impl KdfArgs {
+    /// Applies the given overrides to `base`, refusing anything below the
+    /// configured minimum or outside the bounds a vault file may carry.
+    fn apply_to(&self, base: KdfParams) -> anyhow::Result<KdfParams> {
+        let params = KdfParams {
+            iterations: self.iterations.unwrap_or(base.iterations),
+            memory_kib: self.memory_kib.unwrap_or(base.memory_kib),
+        };
+        check_kdf(params)?;
+        Ok(params)
+    }
+}
 
-    Ok(EncryptedFile {
-        salt_b64: general_purpose::STANDARD.encode(salt.as_ref()),
-        kdf_iterations: iters,
-        kdf_memory_kib: memory_kib,
-        blob_b64: general_purpose::STANDARD.encode(&blob),
+fn check_kdf(params: KdfParams) -> anyhow::Result<()> {
+    let min = KdfParams::minimum()?;
+    if !params.is_within_bounds() || params.is_below(&min) {
+        anyhow::bail!(
+            "KDF parameters must be between iterations={}, memory_kib={} and iterations={}, memory_kib={}",
+            min.iterations,
+            min.memory_kib,
+            kdf::MAX_ITERATIONS,
+            kdf::MAX_MEMORY_KIB
+        );
+    }
+    Ok(())
+}
+
+/// Global options every command needs to reach the vault.
+struct Opts {
+    db: PathBuf,
+    lock_timeout: Duration,
+    keyfile: Option<PathBuf>,
+    recovery_key: bool,
+    prompt: Prompt,
+    /// The agent's socket, unless `--no-agent` or `--recovery-key` was given.
+    agent: Option<PathBuf>,
+}
+
+impl Opts {
+    fn lock(&self, mode: LockMode) -> Result<VaultLock, LockError> {
+        VaultLock::acquire(&self.db, mode, self.lock_timeout)
+    }
+
+    fn open(&self, mode: LockMode) -> Result<VaultHandle, Error> {
+        VaultHandle::open(&self.db, mode, self.lock_timeout)
+    }
+
+    /// Prompts for the recovery key if `--recovery-key` was given. Otherwise
+    /// reads `--keyfile` and prompts for the master password unless `vault`
+    /// has a slot the keyfile opens on its own.
+    fn credential(&self, vault: &Path) -> anyhow::Result<Credential> {
+        if self.recovery_key {
+            let words = self.prompt.ask("Recovery key")?;
+            let key = recovery::parse_mnemonic(&words).map_err(anyhow::Error::msg)?;
+            return Ok(Credential::RecoveryKey(key));
+        }
+        let Some(keyfile) = read_keyfile(self.keyfile.as_deref())? else {
+            let slots = header_slots(vault);
+            if !slots.is_empty() && slots.iter().all(|s| s.kind.needs_keyfile()) {
+                return Err(LoadError::KeyfileRequired.into());
+            }
+            return Ok(Credential::Password(self.prompt.master()?));
+        };
+        if header_slots(vault)
+            .iter()
+            .any(|s| s.kind == SlotKind::Keyfile)
+        {
+            return Ok(Credential::Keyfile(keyfile));
+        }
+        Ok(Credential::PasswordAndKeyfile(
+            self.prompt.master()?,
+            keyfile,
+        ))
+    }
+}
+
+fn read_keyfile(path: Option<&Path>) -> anyhow::Result<Option<Keyfile>> {
+    path.map(|path| {
+        Keyfile::read(path).with_context(|| format!("unable to read keyfile {}", path.display()))
     })
+    .transpose()
 }
 
-fn decrypt_store(enc: &EncryptedFile, master: &str) -> anyhow::Result<Store> {
-    let salt_bytes = general_purpose::STANDARD
-        .decode(&enc.salt_b64)
-        .context("decoded_salt")?;
-    let salt = Salt::from_slice(&salt_bytes)?;
-    let blob = general_purpose::STANDARD
-        .decode(&enc.blob_b64)
-        .context("decode_blob")?;
+/// The header of the vault file at `path`, or `None` if it does not parse;
+/// reading it properly reports the problem.
+fn header(path: &Path) -> Option<Header> {
+    let Ok(VaultDocument::Encrypted(enc)) = fs::read(path)
+        .map_err(LoadError::Io)
+        .and_then(|bytes| format::parse(&bytes))
+    else {
+        return None;
+    };
+    Some(enc.header)
+}
 
-    let password = Password::from_slice(master.as_bytes())?;
-    let dk = kdf::derive_key(&password, &salt, enc.kdf_iterations, enc.kdf_memory_kib, 32)?;
-    let key = orion::aead::SecretKey::from_slice(dk.unprotected_as_bytes())?;
+/// The key slots in the vault file at `path`, or none if it does not parse.
+fn header_slots(path: &Path) -> Vec<KeySlot> {
+    header(path).map(|h| h.key_slots).unwrap_or_default()
+}
 
-    let plaintext = aead::open(&key, &blob).context("decryption_failed")?;
+/// Gathers the secrets of a new key slot: a new master password from
+/// `master` unless `no_password`, plus the keyfile at `keyfile` if one is
+/// given.
+fn new_credential(
+    keyfile: Option<&Path>,
+    no_password: bool,
+    master: impl FnOnce() -> anyhow::Result<SecretString>,
+) -> anyhow::Result<Credential> {
+    let keyfile = read_keyfile(keyfile)?;
+    Ok(match keyfile {
+        Some(keyfile) if no_password => Credential::Keyfile(keyfile),
+        Some(keyfile) => Credential::PasswordAndKeyfile(master()?, keyfile),
+        None => Credential::Password(master()?),
+    })
+}
 
-    Ok(serde_json::from_slice(&plaintext).context("deserialize error")?)
+/// An unlocked vault and the credential it was unlocked with. The lock is
+/// held until this is dropped.
+struct Unlocked {
+    credential: Credential,
+    handle: VaultHandle,
+    agent: Option<PathBuf>,
 }
 
-#[derive(Debug, Parser)]
-#[command(name = "locbox", version, about = "Lightweight CLI password manager.")]
-struct Cli {
-    db: Option<PathBuf>,
+impl Unlocked {
+    fn store_mut(&mut self) -> &mut Store {
+        self.handle.store_mut().expect("unlocked")
+    }
 
-    #[command(subcommand)]
-    command: Commands,
+    fn keyring(&self) -> &Keyring {
+        self.handle.keyring().expect("unlocked")
+    }
+
+    /// Saves the vault and hands the agent the key of the slot it is now
+    /// opened through, which a re-wrap may have changed.
+    fn save(&self) -> anyhow::Result<()> {
+        note_stale(&self.handle.save()?);
+        self.give_agent_key();
+        Ok(())
+    }
+
+    /// Like [`save`](Self::save), but keeps no backup.
+    fn save_without_backup(&self) -> anyhow::Result<()> {
+        note_stale(&self.handle.save_without_backup()?);
+        self.give_agent_key();
+        Ok(())
+    }
+
+    fn give_agent_key(&self) {
+        if let (Some(socket), Some(key)) = (&self.agent, self.keyring().slot_key()) {
+            agent::store(socket, self.keyring().vault_id(), key);
+        }
+    }
 }
 
-#[derive(Debug, Subcommand)]
-enum Commands {
-    Add {
-        service: String,
-        username: String,
-        password: String,
-    },
-    Remove {
-        id: usize,
-    },
-    List,
+/// Mentions the files of interrupted saves that a save cleared away.
+fn note_stale(stale: &[PathBuf]) {
+    for path in stale {
+        eprintln!(
+            "Removed {} left behind by an interrupted save",
+            path.display()
+        );
+    }
 }
 
-fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
-    let db_path = cli.db.unwrap_or_else(|| PathBuf::from("db.json"));
+/// Locks the vault and opens it with the key held by the agent, or else with
+/// credentials asked for, which the agent is then given. When locked for
+/// writing, a slot from an older release or below the configured KDF minimum
+/// is re-wrapped so the next save upgrades it.
+fn unlock(opts: &Opts, mode: LockMode) -> anyhow::Result<Unlocked> {
+    open(opts, mode, true)
+}
+
+/// Like [`unlock`], but always asks for the credentials, for commands that
+/// re-wrap the slot under them, replace them or add another way in. Reaching
+/// the agent must not be enough for any of those.
+fn unlock_with_secret(opts: &Opts, mode: LockMode) -> anyhow::Result<Unlocked> {
+    open(opts, mode, false)
+}
+
+fn open(opts: &Opts, mode: LockMode, use_cached: bool) -> anyhow::Result<Unlocked> {
+    let mut handle = opts.open(mode)?;
+    let agent = opts.agent.as_deref();
+    let cached = agent
+        .filter(|_| use_cached)
+        .zip(header(&opts.db))
+        .and_then(|(socket, header)| agent::fetch(socket, &header.vault_id))
+        .map(Credential::Cached)
+        .map(|credential| (handle.unlock(&credential), credential));
+    let credential = match cached {
+        Some((Ok(()), credential)) => credential,
+        // The slot was re-wrapped or removed since the agent got its key.
+        Some((Err(Error::Load(LoadError::WrongPassword | LoadError::KeyfileRequired)), _))
+        | None => {
+            let credential = opts.credential(&opts.db)?;
+            handle.unlock(&credential)?;
+            let keyring = handle.keyring()?;
+            if let (Some(socket), Some(key)) = (agent, keyring.slot_key()) {
+                agent::store(socket, keyring.vault_id(), key);
+            }
+            credential
+        }
+        Some((Err(e), _)) => return Err(e.into()),
+    };
+    let min = KdfParams::minimum()?;
+    if handle.unlocked_kdf_params()?.is_below(&min) {
+        eprintln!(
+            "Note: vault KDF parameters are below the configured minimum and will be upgraded on the next save"
+        );
+    }
+    handle.upgrade_unlocked_slot(&credential, &min)?;
+    Ok(Unlocked {
+        credential,
+        handle,
+        agent: opts.agent.clone(),
+    })
+}
+
+/// Locks the vault again and opens it with the credential it was opened with
+/// before, asking for it only if that no longer works.
+fn reopen(opts: &Opts, credential: Credential, mode: LockMode) -> anyhow::Result<Unlocked> {
+    let mut handle = opts.open(mode)?;
+    match handle.unlock(&credential) {
+        Ok(()) => {}
+        // The slot was re-wrapped or replaced in the meantime.
+        Err(Error::Load(
+            LoadError::WrongPassword
+            | LoadError::WrongKeyfile
+            | LoadError::WrongRecoveryKey
+            | LoadError::KeyfileRequired,
+        )) => {
+            drop(handle);
+            return unlock(opts, mode);
+        }
+        Err(e) => return Err(e.into()),
+    }
+    handle.upgrade_unlocked_slot(&credential, &KdfParams::minimum()?)?;
+    Ok(Unlocked {
+        credential,
+        handle,
+        agent: opts.agent.clone(),
+    })
+}
+
+/// Asks before `passwd` revokes the key slots `others`, and fails unless
+/// told to go ahead.
+fn confirm_revoke(others: &[String]) -> anyhow::Result<()> {
+    let listed = others.join(", ");
+    let choose = "pass --revoke-recovery to go ahead or --keep-recovery to keep them";
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!(
+            "a new password replaces the data key, which revokes key slots {listed}; {choose}"
+        );
+    }
+    eprintln!("A new password replaces the data key, which revokes key slots {listed}.");
+    eprint!("Revoke them? [y/N] ");
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        anyhow::bail!("nothing was changed; {choose}");
+    }
+    Ok(())
+}
 
-    let mut master = prompt_password("Master password: ")?;
-    let mut store = Store::load(&db_path, &master);
+/// Prompts twice for a new master password and checks its strength.
+fn prompt_new_master(prompt: &Prompt) -> anyhow::Result<SecretString> {
+    let master = prompt.ask("New master password")?;
+    let confirm = prompt.ask("Confirm master password")?;
+    if *master != *confirm {
+        anyhow::bail!("passwords do not match");
+    }
+    strength::check(&master).map_err(anyhow::Error::msg)?;
+    Ok(master)
+}
+
+/// The master password of a vault being created: the given one if there is
+/// one, since nobody may be there to type it, otherwise a new one prompted for.
+fn first_master(prompt: &Prompt) -> anyhow::Result<SecretString> {
+    let Some(master) = prompt.given_master() else {
+        return prompt_new_master(prompt);
+    };
+    let master = master?;
+    strength::check(&master).map_err(anyhow::Error::msg)?;
+    Ok(master)
+}
+
+fn init_vault(opts: &Opts, kdf: &KdfArgs, no_password: bool) -> anyhow::Result<output::Created> {
+    let path = &opts.db;
+    if path.exists() {
+        return Err(Error::AlreadyExists(path.clone()).into());
+    }
+    let params = kdf.apply_to(KdfParams::default())?;
+    let credential = new_credential(opts.keyfile.as_deref(), no_password, || {
+        first_master(&opts.prompt)
+    })?;
+    let handle = VaultHandle::create(path, &credential, params, opts.lock_timeout)?;
+    Ok(output::Created {
+        path: path.display().to_string(),
+        vault_id: handle.keyring()?.vault_id().to_owned(),
+    })
+}
+
+fn given_master(cli: &Cli) -> Option<GivenMaster> {
+    if let Some(path) = &cli.master_password_file {
+        return Some(GivenMaster::File(path.clone()));
+    }
+    if let Some(fd) = cli.master_password_fd {
+        return Some(GivenMaster::Fd(fd));
+    }
+    std::env::var(input::MASTER_PASSWORD_CMD_ENV)
+        .ok()
+        .filter(|command| !command.trim().is_empty())
+        .map(GivenMaster::Command)
+}
+
+fn run(cli: Cli, out: &Printer) -> anyhow::Result<()> {
+    let prompt = Prompt::new(given_master(&cli), cli.pinentry.clone());
+    let opts = Opts {
+        db: cli.db.unwrap_or_else(|| PathBuf::from("db.json")),
+        lock_timeout: Duration::from_secs(cli.lock_timeout),
+        keyfile: cli.keyfile,
+        recovery_key: cli.recovery_key,
+        prompt,
+        agent: (!cli.no_agent && !cli.recovery_key).then(agent::socket_path),
+    };
+    let db_path = &opts.db;
 
     match cli.command {
+        Commands::Init { kdf, no_password } => out.one(&init_vault(&opts, &kdf, no_password)?)?,
+        Commands::Passwd {
+            revoke_recovery,
+            keep_recovery,
+        } => {
+            // The old password is always asked for, so an agent holding the
+            // key is not enough to take the vault over.
+            let mut vault = unlock_with_secret(&opts, LockMode::Exclusive)?;
+            let current = vault.keyring().unlocked_slot().map(|s| s.id.clone());
+            let others = vault
+                .keyring()
+                .slots()
+                .iter()
+                .filter(|s| Some(&s.id) != current.as_ref())
+                .map(|s| format!("{} ({})", s.id, s.label))
+                .collect::<Vec<_>>();
+            if !keep_recovery && !revoke_recovery && !others.is_empty() {
+                confirm_revoke(&others)?;
+            }
+            let credential = match vault.credential.kind() {
+                SlotKind::Password => Credential::Password(prompt_new_master(&opts.prompt)?),
+                SlotKind::PasswordKeyfile => {
+                    let keyfile = read_keyfile(opts.keyfile.as_deref())?.ok_or_else(|| {
+                        anyhow::anyhow!("pass the --keyfile the new password goes with")
+                    })?;
+                    Credential::PasswordAndKeyfile(prompt_new_master(&opts.prompt)?, keyfile)
+                }
+                SlotKind::Keyfile | SlotKind::Shamir | SlotKind::RecoveryKey => anyhow::bail!(
+                    "the key slot used to unlock has no password; add one with `lockbox slot add`"
+                ),
+            };
+            let revoked = if keep_recovery {
+                vault.handle.rewrap_credential(&credential)?;
+                vec![]
+            } else {
+                // A new data key, so an old copy of the file and the old
+                // password cannot open anything saved from now on.
+                vault.handle.change_credential(&credential)?
+            };
+            vault.save()?;
+            out.one(&output::PasswordChanged {
+                slot_id: vault
+                    .keyring()
+                    .unlocked_slot()
+                    .map(|s| s.id.clone())
+                    .unwrap_or_default(),
+                revoked_slots: revoked.into_iter().map(|s| s.id).collect(),
+            })?;
+            if keep_recovery {
+                eprintln!(
+                    "Note: the data key was kept, so copies of the vault from before still open with the old password"
+                );
+            } else if !backup::list(db_path)?.is_empty() {
+                eprintln!(
+                    "Note: existing backups in {} still open with the old password",
+                    backup::dir(db_path).display()
+                );
+            }
+        }
         Commands::Add {
-            service,
-            username,
-            password,
+            kind,
+            login,
+            details,
         } => {
-            let id = store.next_id;
-            store.next_id += 1;
-            store
-                .vault_items
-                .push(Vault::new(id, service, username, password));
-            let pushed = store.vault_items.last().expect("Just pushed");
-            println!("Added Entry with ID: {}", pushed.id);
-            store.save(&db_path, &master)?;
+            let (service, data, details, generated_length) = match kind {
+                Some(kind) => kind.read()?,
+                None => {
+                    let (service, data, generated) = login.read()?;
+                    (service, data, details, generated)
+                }
+            };
+            // Checked before unlocking, so a typo costs no password prompt.
+            data.validate()?;
+            let fields = details.custom_fields()?;
+            let expired = data.is_expired(Date::today());
+            let kind = data.tag();
+            let mut vault = unlock(&opts, LockMode::Exclusive)?;
+            let id = vault.handle.add_typed_entry(service, data)?;
+            details.apply(vault.handle.entry_mut(id)?, fields);
+            vault.save()?;
+            out.one(&output::Added {
+                id,
+                generated_length,
+                kind,
+            })?;
+            if expired {
+                eprintln!("Note: entry {id} has already expired");
+            }
         }
         Commands::Remove { id } => {
-            if let Some(pos) = store.vault_items.iter().position(|v| v.id == id) {
-                store.vault_items.remove(pos);
-                store.save(&db_path, &master)?;
-                println!("Removed Service with ID: {id}");
+            let mut vault = unlock(&opts, LockMode::Exclusive)?;
+            let removed = match vault.handle.remove_entry(id) {
+                Ok(_) => {
+                    vault.save()?;
+                    true
+                }
+                Err(Error::NoSuchEntry(_)) => false,
+                Err(e) => return Err(e.into()),
+            };
+            out.one(&output::Removed { id, removed })?;
+        }
+        Commands::List => {
+            let vault = unlock(&opts, LockMode::Shared)?;
+            let entries = vault.handle.entries()?;
+            out.list(&entries.iter().map(output::Entry::from).collect::<Vec<_>>())?;
+        }
+        Commands::MigratePlaintext => {
+            let _lock = opts.lock(LockMode::Exclusive)?;
+            out.one(&migrate::migrate_plaintext(db_path, &opts.prompt)?)?
+        }
+        Commands::Backup { command } => run_backup(&opts, command, out)?,
+        Commands::Kdf { command } => run_kdf(&opts, command, out)?,
+        Commands::Slot { command } => run_slot(&opts, command, out)?,
+        Commands::Keyfile {
+            command: KeyfileCommand::Generate { path },
+        } => {
+            keyfile::generate(&path)
+                .with_context(|| format!("unable to create keyfile {}", path.display()))?;
+            out.one(&output::KeyfileWritten {
+                path: path.display().to_string(),
+            })?;
+            eprintln!("Keep a copy somewhere safe: without it, slots that need it cannot unlock");
+        }
+        Commands::Recovery { command } => run_recovery(&opts, command, out)?,
+        Commands::History { command, id } => run_history(&opts, command, id, out)?,
+        Commands::Get {
+            entry,
+            reveal,
+            field,
+        } => {
+            let revealed = reveal || field.is_some();
+            let mode = if revealed {
+                LockMode::Exclusive
             } else {
-                println!("Unable to find service with the ID: {id}");
+                LockMode::Shared
+            };
+            let mut vault = unlock(&opts, mode)?;
+            let found = vault.handle.find_entry(&entry)?;
+            let id = found.id;
+            let shown = output::Shown::new(found, revealed);
+            let value = field
+                .as_deref()
+                .map(|name| {
+                    shown
+                        .field(name)?
+                        .ok_or_else(|| anyhow::anyhow!("entry {id} has no {name}"))
+                })
+                .transpose()?;
+            // Only reading what `get` otherwise masks is a use.
+            let used = match &field {
+                Some(name) => output::Shown::new(found, false).field(name)? != value,
+                None => reveal,
+            };
+            if used {
+                vault.handle.mark_used(id)?;
+                // Recording the use is not worth withholding the secret for.
+                if let Err(e) = vault.save_without_backup() {
+                    eprintln!("Note: the use of entry {id} was not recorded: {e:#}");
+                }
+            }
+            match value {
+                Some(value) => {
+                    let mut stdout = std::io::stdout().lock();
+                    stdout.write_all(value.as_bytes())?;
+                    stdout.write_all(b"\n")?;
+                }
+                None => out.one(&shown)?,
             }
         }
-        Commands::List => {
-            for i in &store.vault_items {
-                println!("{} | {} | {} | {}", i.id, i.service, i.username, i.password);
+        Commands::Edit {
+            entry,
+            interactive,
+            editor,
+            changes,
+            source,
+            details,
+        } => {
+            if !interactive && !editor && changes.is_empty(&source, &details) {
+                anyhow::bail!(
+                    "nothing to change; give the new values as flags, or --interactive or --editor"
+                );
+            }
+            let (mut vault, id, edited) = if editor {
+                // The editor may stay open for long, so the vault is only
+                // locked again to save, once nobody changed the entry since.
+                let vault = unlock(&opts, LockMode::Shared)?;
+                let read = vault.handle.find_entry(&entry)?.clone();
+                let credential = vault.credential;
+                drop(vault.handle);
+                let edited = edit::in_editor(&read)?;
+                let vault = reopen(&opts, credential, LockMode::Exclusive)?;
+                let current = vault.handle.entry(read.id).map_err(|_| {
+                    anyhow::anyhow!(
+                        "entry {} was removed in the meantime; nothing was changed",
+                        read.id
+                    )
+                })?;
+                if current.modified_ms != read.modified_ms
+                    || current.data != read.data
+                    || !current.same_details(&read)
+                {
+                    anyhow::bail!(
+                        "entry {} was changed in the meantime; nothing was changed, edit it again",
+                        read.id
+                    );
+                }
+                (vault, read.id, edited)
+            } else {
+                let vault = unlock(&opts, LockMode::Exclusive)?;
+                let current = vault.handle.find_entry(&entry)?;
+                let id = current.id;
+                let edited = if interactive {
+                    edit::interactive(current)?
+                } else {
+                    changes.apply(current, &source, &details)?
+                };
+                (vault, id, edited)
+            };
+            let changed = vault.handle.edit_entry(id, edited)?;
+            if changed {
+                vault.save()?;
+            }
+            out.one(&output::Edited { id, changed })?;
+        }
+        Commands::EmergencyKit { html, output } => {
+            emergency_kit(&opts, html, output.as_deref(), out)?
+        }
+        Commands::Agent { idle_timeout } => {
+            agent::serve(&agent::socket_path(), Duration::from_secs(idle_timeout))?
+        }
+        Commands::Lock => {
+            let locked = agent::lock(&agent::socket_path())?;
+            out.one(&output::AgentLocked { locked })?;
+        }
+    }
+
+    Ok(())
+}
+
+fn run_backup(opts: &Opts, command: BackupCommand, out: &Printer) -> anyhow::Result<()> {
+    let db_path = &opts.db;
+    match command {
+        BackupCommand::List => {
+            let vault = unlock(opts, LockMode::Shared)?;
+            let backups = backup::list(db_path)?
+                .iter()
+                .map(|b| output::BackupInfo {
+                    id: b.id(),
+                    taken: timestamp::format_utc(b.taken_ms / 1000),
+                    entries: fs::read(&b.path)
+                        .ok()
+                        .and_then(|bytes| Store::decrypt(&bytes, &vault.credential).ok())
+                        .map(|(s, _)| s.vault_items.len()),
+                })
+                .collect::<Vec<_>>();
+            out.list(&backups)?;
+        }
+        BackupCommand::Restore { id } => {
+            // The current file may be the reason for restoring, so only the
+            // backup has to open with the password.
+            let _lock = opts.lock(LockMode::Exclusive)?;
+            let chosen = backup::find(db_path, &id)?;
+            let credential = opts.credential(&chosen.path)?;
+            let (restored, _) = Store::decrypt(&fs::read(&chosen.path)?, &credential)
+                .with_context(|| format!("backup {id} does not open"))?;
+            backup::restore(
+                db_path,
+                &id,
+                &restored.settings.backup,
+                timestamp::now_millis(),
+            )?;
+            out.one(&output::Restored {
+                id,
+                taken: timestamp::format_utc(chosen.taken_ms / 1000),
+                entries: restored.vault_items.len(),
+            })?;
+        }
+        BackupCommand::Policy {
+            keep_last,
+            daily,
+            weekly,
+        } => {
+            let changed = keep_last.is_some() || daily.is_some() || weekly.is_some();
+            let mode = if changed {
+                LockMode::Exclusive
+            } else {
+                LockMode::Shared
+            };
+            let mut vault = unlock(opts, mode)?;
+            let policy = &mut vault.store_mut().settings.backup;
+            policy.keep_last = keep_last.unwrap_or(policy.keep_last);
+            policy.daily = daily.unwrap_or(policy.daily);
+            policy.weekly = weekly.unwrap_or(policy.weekly);
+            let policy = *policy;
+            if changed {
+                vault.save()?;
             }
+            out.one(&policy)?;
         }
     }
+    Ok(())
+}
 
-    master.zeroize();
+fn run_history(
+    opts: &Opts,
+    command: Option<HistoryCommand>,
+    id: Option<usize>,
+    out: &Printer,
+) -> anyhow::Result<()> {
+    match command {
+        None => {
+            let id = id.expect("required by the parser");
+            let vault = unlock(opts, LockMode::Shared)?;
+            let versions = vault
+                .handle
+                .entry(id)?
+                .history
+                .iter()
+                .enumerate()
+                .map(|(i, past)| output::Version::new(id, i + 1, past))
+                .collect::<Vec<_>>();
+            out.list(&versions)?;
+        }
+        Some(HistoryCommand::Restore { id, number }) => {
+            let mut vault = unlock(opts, LockMode::Exclusive)?;
+            vault.handle.restore_entry_version(id, number)?;
+            vault.save()?;
+            out.one(&output::VersionRestored { id, number })?;
+        }
+        Some(HistoryCommand::Policy {
+            keep_last,
+            max_age_days,
+        }) => {
+            let changed = keep_last.is_some() || max_age_days.is_some();
+            let mode = if changed {
+                LockMode::Exclusive
+            } else {
+                LockMode::Shared
+            };
+            let mut vault = unlock(opts, mode)?;
+            let store = vault.store_mut();
+            let policy = &mut store.settings.history;
+            policy.keep_last = keep_last.unwrap_or(policy.keep_last);
+            policy.max_age_days = max_age_days.unwrap_or(policy.max_age_days);
+            let policy = *policy;
+            if changed {
+                // Apply the new limits to what is already kept.
+                let now = timestamp::now_millis();
+                for entry in &mut store.vault_items {
+                    policy.prune(&mut entry.history, now);
+                }
+                vault.save()?;
+            }
+            out.one(&policy)?;
+        }
+    }
     Ok(())
 }
+
+fn run_kdf(opts: &Opts, command: KdfCommand, out: &Printer) -> anyhow::Result<()> {
+    match command {
+        KdfCommand::Show => {
+            let vault = unlock(opts, LockMode::Shared)?;
+            let params = vault.handle.unlocked_kdf_params()?;
+            let min = KdfParams::minimum()?;
+            out.one(&output::KdfInfo {
+                iterations: params.iterations,
+                memory_kib: params.memory_kib,
+                min_iterations: min.iterations,
+                min_memory_kib: min.memory_kib,
+            })?;
+        }
+        KdfCommand::Set { kdf } => {
+            let mut vault = unlock_with_secret(opts, LockMode::Exclusive)?;
+            let params = kdf.apply_to(vault.handle.unlocked_kdf_params()?)?;
+            vault.handle.set_kdf(&vault.credential, params)?;
+            vault.save()?;
+            out.one(&output::KdfSet {
+                iterations: params.iterations,
+                memory_kib: params.memory_kib,
+            })?;
+        }
+        KdfCommand::Calibrate {
+            target_ms,
+            memory_kib,
+            apply,
+        } => {
+            let (params, took) = kdf::calibrate(
+                Duration::from_millis(target_ms),
+                memory_kib,
+                &KdfParams::minimum()?,
+            )?;
+            if apply {
+                check_kdf(params)?;
+                let mut vault = unlock_with_secret(opts, LockMode::Exclusive)?;
+                vault.handle.set_kdf(&vault.credential, params)?;
+                vault.save()?;
+            }
+            out.one(&output::Calibrated {
+                iterations: params.iterations,
+                memory_kib: params.memory_kib,
+                millis: took.as_millis(),
+                applied: apply,
+            })?;
+        }
+    }
+    Ok(())
+}
+
+fn run_recovery(opts: &Opts, command: RecoveryCommand, out: &Printer) -> anyhow::Result<()> {
+    match command {
+        RecoveryCommand::Split {
+            threshold,
+            shares,
+            encoding,
+        } => {
+            let mut vault = unlock_with_secret(opts, LockMode::Exclusive)?;
+            // Plain Argon2 cost is enough: the secret is random, not guessable.
+            let split =
+                vault
+                    .handle
+                    .add_recovery_shares(threshold, shares, KdfParams::minimum()?)?;
+            let slot_id = split[0].slot_id.clone();
+            vault.save()?;
+
+            let infos = split
+                .iter()
+                .map(|share| output::ShareInfo {
+                    number: share.number(),
+                    shares,
+                    threshold,
+                    slot_id: slot_id.clone(),
+                    share: SecretString::new(share.encode(encoding).to_string()),
+                })
+                .collect::<Vec<_>>();
+            out.list(&infos)?;
+            eprintln!(
+                "Give each share to a different person. Any {threshold} of them unlock the vault through key slot {slot_id}; remove that slot to revoke them."
+            );
+        }
+        RecoveryCommand::Combine => {
+            let mut handle = opts.open(LockMode::Exclusive)?;
+            let slot_id = handle.unlock_with_shares(read_shares()?)?;
+
+            eprintln!("Vault unlocked through key slot {slot_id}; choose a new master password.");
+            let master = prompt_new_master(&opts.prompt)?;
+            let params = KdfParams::default().at_least(&KdfParams::minimum()?);
+            let revoked = handle.reset_credentials(
+                &Credential::Password(master),
+                params,
+                "master password",
+            )?;
+            note_stale(&handle.save()?);
+            out.one(&output::Recovered {
+                slot_id,
+                revoked_slots: revoked.into_iter().map(|s| s.id).collect(),
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Adds a recovery key slot, revoking any earlier kit's, and writes the kit
+/// before saving so the key cannot be lost between the two.
+fn emergency_kit(
+    opts: &Opts,
+    html: bool,
+    output: Option<&Path>,
+    out: &Printer,
+) -> anyhow::Result<()> {
+    let db_path = &opts.db;
+    let mut vault = unlock_with_secret(opts, LockMode::Exclusive)?;
+    let secret = recovery::new_secret()?;
+    // Plain Argon2 cost is enough: the key is random, not guessable.
+    let (slot, revoked) = vault
+        .handle
+        .replace_recovery_key(&secret, KdfParams::minimum()?)?;
+    let slot_id = slot.id;
+    let earlier = revoked.into_iter().map(|s| s.id).collect::<Vec<_>>();
+
+    let vault_path = fs::canonicalize(db_path)?;
+    let mnemonic = recovery::mnemonic(&secret);
+    let kit = kit::Kit {
+        vault_id: vault.keyring().vault_id(),
+        vault_path: &vault_path.to_string_lossy(),
+        slots: vault.keyring().slots(),
+        recovery_slot_id: &slot_id,
+        mnemonic: &mnemonic,
+        created_ms: timestamp::now_millis(),
+    };
+    let sheet = Zeroizing::new(if html { kit.to_html() } else { kit.to_text() });
+    let printed = output.is_none();
+    let info = output::KitInfo {
+        vault_id: vault.keyring().vault_id().to_owned(),
+        slot_id,
+        recovery_key: printed.then(|| SecretString::new(mnemonic.to_string())),
+        path: output.map(|p| p.display().to_string()),
+        revoked_slots: earlier,
+        sheet: printed.then(|| SecretString::new(sheet.to_string())),
+    };
+    match output {
+        Some(path) => {
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let mut f = options
+                .open(path)
+                .with_context(|| format!("unable to create {}", path.display()))?;
+            std::io::Write::write_all(&mut f, sheet.as_bytes())?;
+            f.sync_all()?;
+            if let Err(e) = vault.save() {
+                let _ = fs::remove_file(path);
+                return Err(e);
+            }
+            eprintln!("Wrote emergency kit to {}", path.display());
+            out.one(&info)?;
+        }
+        None => {
+            out.one(&info)?;
+            std::io::stdout().flush()?;
+            vault
+                .save()
+                .context("the vault was not saved, so the kit printed does not open it")?;
+        }
+    }
+    if !info.revoked_slots.is_empty() {
+        eprintln!(
+            "Revoked the recovery key of the previous kit (slot {})",
+            info.revoked_slots.join(", ")
+        );
+    }
+    eprintln!("Print the kit, store it offline, and delete any digital copy.");
+    Ok(())
+}
+
+/// Reads shares from stdin, one per line, until the threshold named in the
+/// first is reached.
+fn read_shares() -> anyhow::Result<Vec<RecoveryShare>> {
+    let stdin = std::io::stdin();
+    let mut shares: Vec<RecoveryShare> = vec![];
+    loop {
+        let needed = shares.first().map_or(1, |s| usize::from(s.threshold));
+        if shares.len() >= needed {
+            return Ok(shares);
+        }
+        eprint!(
+            "Share {} of {}: ",
+            shares.len() + 1,
+            if shares.is_empty() {
+                "?".to_owned()
+            } else {
+                needed.to_string()
+            }
+        );
+        let mut line = Zeroizing::new(String::new());
+        if stdin.read_line(&mut line)? == 0 {
+            anyhow::bail!("{} of {needed} shares given", shares.len());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        match RecoveryShare::parse(&line) {
+            Ok(share) if shares.iter().any(|s| s.number() == share.number()) => {
+                eprintln!("Share {} was already entered", share.number());
+            }
+            Ok(share) => shares.push(share),
+            Err(e) => eprintln!("Not accepted: {e}"),
+        }
+    }
+}
+
+fn run_slot(opts: &Opts, command: SlotCommand, out: &Printer) -> anyhow::Result<()> {
+    let db_path = &opts.db;
+    match command {
+        SlotCommand::List => {
+            let vault = unlock(opts, LockMode::Shared)?;
+            let current = vault.keyring().unlocked_slot().map(|s| s.id.clone());
+            let slots = vault
+                .keyring()
+                .slots()
+                .iter()
+                .map(|slot| output::SlotInfo {
+                    unlocked: Some(&slot.id) == current.as_ref(),
+                    id: slot.id.clone(),
+                    kind: slot.kind,
+                    label: slot.label.clone(),
+                    created: timestamp::format_utc(slot.created_ms / 1000),
+                    kdf_iterations: slot.kdf_iterations,
+                    kdf_memory_kib: slot.kdf_memory_kib,
+                })
+                .collect::<Vec<_>>();
+            out.list(&slots)?;
+        }
+        SlotCommand::Add {
+            label,
+            with_keyfile,
+            no_password,
+        } => {
+            let mut vault = unlock_with_secret(opts, LockMode::Exclusive)?;
+            let credential = new_credential(with_keyfile.as_deref(), no_password, || {
+                prompt_new_master(&opts.prompt)
+            })?;
+            let params = KdfParams::default().at_least(&KdfParams::minimum()?);
+            let slot = vault.handle.add_slot(&credential, params, &label)?;
+            vault.save()?;
+            out.one(&output::SlotChanged {
+                id: slot.id,
+                kind: slot.kind,
+                removed: false,
+            })?;
+        }
+        SlotCommand::Remove { id } => {
+            let mut vault = unlock(opts, LockMode::Exclusive)?;
+            let removed = vault.handle.remove_slot(&id)?;
+            vault.save()?;
+            out.one(&output::SlotChanged {
+                id: removed.id,
+                kind: removed.kind,
+                removed: true,
+            })?;
+            if !backup::list(db_path)?.is_empty() {
+                eprintln!(
+                    "Note: existing backups in {} can still be opened through the removed slot",
+                    backup::dir(db_path).display()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    secret::harden_process();
+    let cli = Cli::parse();
+    let out = Printer::new(cli.format);
+    match run(cli, &out) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let failure = failure(&e);
+            out.error(&failure);
+            ExitCode::from(failure.exit_code)
+        }
+    }
+}
+
+fn failure(e: &anyhow::Error) -> output::Failure {
+    let (code, exit_code) = if let Some(e) = e.downcast_ref::<Error>() {
+        (e.code(), e.exit_code())
+    } else if let Some(e) = e.downcast_ref::<LoadError>() {
+        (e.code(), e.exit_code())
+    } else if let Some(e) = e.downcast_ref::<LockError>() {
+        (e.code(), e.exit_code())
+    } else {
+        ("error", 1)
+    };
+    output::Failure {
+        code,
+        exit_code,
+        message: format!("{e:#}"),
+    }
+}
@@ -0,0 +1,135 @@
+//! One-off conversion of an unencrypted `Store` file into an encrypted vault.
+//!
+//! Very early releases wrote the store as plain JSON. Those files are refused
+//! by [`Store::load`]; this is the only path that reads them, and it leaves no
+//! plaintext behind in the file it converts.
+
+use crate::{first_master, input::Prompt, output::Migrated};
+use anyhow::Context;
+use lockbox::{
+    KdfParams, Store, atomic, crypto, decrypt_store, encrypt_store,
+    error::LoadError,
+    format::{self, VaultDocument},
+    keyring::{Credential, Keyring},
+    secret::SecretBuf,
+};
+use orion::util::secure_rand_bytes;
+use std::{
+    ffi::OsStr,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+use zeroize::Zeroizing;
+
+/// Suffixes editors, shells and older lockbox releases leave next to a file.
+const COPY_SUFFIXES: &[&str] = &[".tmp", ".bak", ".old", ".orig", ".backup", "~", ".swp"];
+
+pub fn migrate_plaintext(path: &PathBuf, prompt: &Prompt) -> anyhow::Result<Migrated> {
+    if !path.exists() {
+        return Err(LoadError::NotFound(path.clone()).into());
+    }
+    let bytes = Zeroizing::new(fs::read(path).map_err(LoadError::Io)?);
+    let value = match format::parse(&bytes)? {
+        VaultDocument::LegacyPlaintext(value) => value,
+        VaultDocument::Encrypted(_) => anyhow::bail!("{} is already encrypted", path.display()),
+    };
+    let store: Store = serde_json::from_value(value)
+        .map_err(|e| LoadError::Corrupt(format!("not a lockbox vault: {e}")))?;
+    let params = KdfParams::default().at_least(&KdfParams::minimum()?);
+
+    let credential = Credential::Password(first_master(prompt)?);
+    let mut keyring = Keyring::new(crypto::random_id()?);
+    keyring.add_slot(&credential, params, "master password")?;
+    let enc = encrypt_store(&store, &keyring)?;
+    let json = serde_json::to_vec_pretty(&enc).context("serialize_vault")?;
+
+    // Decrypt what we are about to write and make sure nothing was lost
+    // before the plaintext is destroyed.
+    let roundtrip = match format::parse(&json)? {
+        VaultDocument::Encrypted(enc) => decrypt_store(&enc, &credential)?.0,
+        VaultDocument::LegacyPlaintext(_) => unreachable!("encrypt_store wrote plaintext"),
+    };
+    if *plaintext_json(&roundtrip)? != *plaintext_json(&store)? {
+        anyhow::bail!(
+            "encrypted vault did not round-trip; {} left untouched",
+            path.display()
+        );
+    }
+
+    let tmp = atomic::TempFile::create(path, &json)?;
+    if fs::read(tmp.path())? != json {
+        anyhow::bail!(
+            "encrypted vault did not read back intact; {} left untouched",
+            path.display()
+        );
+    }
+
+    // Opened before the encrypted vault is renamed over it, so the plaintext
+    // can be shredded afterwards without `path` ever lacking an intact vault.
+    let mut plaintext = OpenOptions::new().write(true).open(path)?;
+    tmp.commit()?;
+    shred(&mut plaintext, bytes.len())
+        .context("the vault is encrypted, but its old plaintext may not be overwritten")?;
+
+    for copy in possible_copies(path)? {
+        eprintln!(
+            "Warning: {} may hold an unencrypted copy of the vault; remove it securely",
+            copy.display()
+        );
+    }
+    eprintln!("Warning: older backups or filesystem snapshots may still hold the plaintext");
+    Ok(Migrated {
+        path: path.display().to_string(),
+        entries: store.vault_items.len(),
+    })
+}
+
+fn plaintext_json(store: &Store) -> anyhow::Result<SecretBuf> {
+    let mut buf = SecretBuf::default();
+    serde_json::to_writer(&mut buf, store)?;
+    Ok(buf)
+}
+
+/// Overwrites the first `len` bytes of `f` in place with random data.
+fn shred(f: &mut File, len: usize) -> anyhow::Result<()> {
+    let mut noise = vec![0u8; len];
+    if len > 0 {
+        secure_rand_bytes(&mut noise)?;
+    }
+    f.write_all(&noise)?;
+    f.sync_all()?;
+    Ok(())
+}
+
+/// Files next to `path` whose names suggest they are copies of it.
+fn possible_copies(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let Some(name) = path.file_name().and_then(OsStr::to_str) else {
+        return Ok(vec![]);
+    };
+    let stem = path.file_stem().and_then(OsStr::to_str).unwrap_or(name);
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+
+    let mut copies = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let Some(other) = entry.file_name().to_str().map(str::to_owned) else {
+            continue;
+        };
+        if other == name {
+            continue;
+        }
+        let is_copy = COPY_SUFFIXES.iter().any(|suffix| {
+            (other.starts_with(name) && other.ends_with(suffix))
+                || other == format!("{stem}{suffix}")
+        }) || other == format!(".{name}.swp");
+        if is_copy {
+            copies.push(entry.path());
+        }
+    }
+    copies.sort();
+    Ok(copies)
+}
@@ -0,0 +1,1107 @@
+//! What commands print, for people or for scripts.
+//!
+//! `--format table`, the default, prints sentences and `|`-separated rows.
+//! The other formats print the same results as data, on standard output:
+//!
+//! - `json`: one JSON value, an array of objects for commands that list
+//!   things and a single object otherwise.
+//! - `jsonl`: one JSON object per line.
+//! - `tsv`: a line of field names, then a line per object with its fields in
+//!   the same order, separated by tabs. Backslash, tab, newline and carriage
+//!   return in values are written `\\`, `\t`, `\n` and `\r`; lists are joined
+//!   with commas, commas within their items written `\,`; objects are
+//!   written as JSON and absent values left empty.
+//!
+//! Each command's fields are those of its report type below. Fields may be
+//! added in later releases but are never renamed or removed.
+//!
+//! Errors go to standard error as [`Failure`]: in `json` and `jsonl` the
+//! object `{"error": {"code": ..., "exit_code": ..., "message": ...}}`, in
+//! `tsv` the failure's own header and row. Notes and warnings stay plain text
+//! on standard error in every format.
+
+use lockbox::{
+    CustomField, EntryData, SecretString, Vault,
+    backup::BackupPolicy,
+    format::SlotKind,
+    history::{HistoryPolicy, PastData},
+    secret::{self, SecretBuf},
+    timestamp,
+};
+use serde::Serialize;
+use std::io::{self, Write};
+use zeroize::Zeroizing;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    #[default]
+    Table,
+    Json,
+    Jsonl,
+    Tsv,
+}
+
+/// A command's result.
+pub trait Report: Serialize {
+    /// The serialized fields, in the order `tsv` prints them.
+    const FIELDS: &'static [&'static str];
+
+    /// The result as `--format table` shows it; nothing is printed if empty.
+    fn table(&self) -> String;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Printer {
+    format: Format,
+}
+
+impl Printer {
+    pub fn new(format: Format) -> Self {
+        Self { format }
+    }
+
+    pub fn one<T: Report>(&self, item: &T) -> anyhow::Result<()> {
+        let out = render(self.format, std::slice::from_ref(item), false)?;
+        io::stdout().lock().write_all(&out)?;
+        Ok(())
+    }
+
+    pub fn list<T: Report>(&self, items: &[T]) -> anyhow::Result<()> {
+        let out = render(self.format, items, true)?;
+        io::stdout().lock().write_all(&out)?;
+        Ok(())
+    }
+
+    pub fn error(&self, failure: &Failure) {
+        let out = match self.format {
+            Format::Table => failure.table() + "\n",
+            Format::Json | Format::Jsonl => {
+                format!("{}\n", serde_json::json!({ "error": failure }))
+            }
+            Format::Tsv => render(Format::Tsv, std::slice::from_ref(failure), false)
+                .map(|out| String::from_utf8_lossy(&out).into_owned())
+                .unwrap_or_default(),
+        };
+        let _ = io::stderr().lock().write_all(out.as_bytes());
+    }
+}
+
+fn render<T: Report>(format: Format, items: &[T], list: bool) -> anyhow::Result<SecretBuf> {
+    let mut out = SecretBuf::default();
+    match format {
+        Format::Table => {
+            for item in items {
+                let text = Zeroizing::new(item.table());
+                if !text.is_empty() {
+                    out.extend_from_slice(text.as_bytes());
+                    if !text.ends_with('\n') {
+                        out.extend_from_slice(b"\n");
+                    }
+                }
+            }
+        }
+        Format::Json if list => {
+            serde_json::to_writer_pretty(&mut out, items)?;
+            out.extend_from_slice(b"\n");
+        }
+        Format::Json => {
+            for item in items {
+                serde_json::to_writer_pretty(&mut out, item)?;
+                out.extend_from_slice(b"\n");
+            }
+        }
+        Format::Jsonl => {
+            for item in items {
+                serde_json::to_writer(&mut out, item)?;
+                out.extend_from_slice(b"\n");
+            }
+        }
+        Format::Tsv => {
+            out.extend_from_slice(T::FIELDS.join("\t").as_bytes());
+            out.extend_from_slice(b"\n");
+            for item in items {
+                let mut value = serde_json::to_value(item)?;
+                let row = T::FIELDS
+                    .iter()
+                    .map(|field| Zeroizing::new(cell(&value[*field])))
+                    .collect::<Vec<_>>();
+                secret::wipe_json(&mut value);
+                for (i, cell) in row.iter().enumerate() {
+                    if i > 0 {
+                        out.extend_from_slice(b"\t");
+                    }
+                    out.extend_from_slice(cell.as_bytes());
+                }
+                out.extend_from_slice(b"\n");
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn cell(value: &serde_json::Value) -> String {
+    use serde_json::Value;
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => escape(s),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| cell(item).replace(',', "\\,"))
+            .collect::<Vec<_>>()
+            .join(","),
+        other => escape(&other.to_string()),
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// A command that failed.
+#[derive(Debug, Serialize)]
+pub struct Failure {
+    /// A stable name such as `wrong-password` or `not-found`; `error` for
+    /// anything without one.
+    pub code: &'static str,
+    /// The process exit status.
+    pub exit_code: u8,
+    pub message: String,
+}
+
+impl Report for Failure {
+    const FIELDS: &'static [&'static str] = &["code", "exit_code", "message"];
+
+    fn table(&self) -> String {
+        format!("Error: {}", self.message)
+    }
+}
+
+/// `list`: one per entry.
+#[derive(Debug, Serialize)]
+pub struct Entry<'a> {
+    pub id: usize,
+    pub service: &'a str,
+    /// Only logins have a username and password.
+    pub username: Option<&'a str>,
+    pub password: Option<&'a SecretString>,
+    pub urls: &'a [String],
+    pub notes: Option<&'a SecretString>,
+    pub tags: &'a [String],
+    /// Objects with `name`, `value` and `secret`.
+    pub fields: &'a [CustomField],
+    /// As `YYYY-MM-DD HH:MM:SS UTC`; absent for entries from older releases.
+    pub created: Option<String>,
+    pub modified: Option<String>,
+    pub last_used: Option<String>,
+    /// `login`, `secure-note`, `card`, `ssh-key`, `api-token` or `wifi`.
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// The type's own values, as an object tagged with `type`.
+    pub details: &'a EntryData,
+}
+
+impl<'a> From<&'a Vault> for Entry<'a> {
+    fn from(v: &'a Vault) -> Self {
+        let utc = |ms: Option<u64>| ms.map(|ms| timestamp::format_utc(ms / 1000));
+        Self {
+            id: v.id,
+            service: &v.service,
+            username: v.username(),
+            password: v.password(),
+            urls: &v.urls,
+            notes: v.notes.as_ref(),
+            tags: &v.tags,
+            fields: &v.fields,
+            created: utc(v.created_ms),
+            modified: utc(v.modified_ms),
+            last_used: utc(v.last_used_ms),
+            kind: v.data.tag(),
+            details: &v.data,
+        }
+    }
+}
+
+impl Report for Entry<'_> {
+    const FIELDS: &'static [&'static str] = &[
+        "id",
+        "service",
+        "username",
+        "password",
+        "urls",
+        "notes",
+        "tags",
+        "fields",
+        "created",
+        "modified",
+        "last_used",
+        "type",
+        "details",
+    ];
+
+    /// Logins as `id | service | username | password`, as they always were;
+    /// other types with their type after the name and then their own values.
+    fn table(&self) -> String {
+        let head = format!("{} | {}", self.id, self.service);
+        let kind = self.details.type_name();
+        match self.details {
+            EntryData::Login { username, password } => {
+                format!("{head} | {username} | {}", &**password)
+            }
+            EntryData::SecureNote { text } => {
+                let first = text.lines().next().unwrap_or_default();
+                let more = if text.lines().nth(1).is_some() {
+                    " …"
+                } else {
+                    ""
+                };
+                format!("{head} | {kind} | {first}{more}")
+            }
+            EntryData::Card {
+                holder,
+                number,
+                expiry,
+                ..
+            } => format!(
+                "{head} | {kind} | {holder} | {} | expires {expiry}",
+                &**number
+            ),
+            EntryData::SshKey { public_key, .. } => format!(
+                "{head} | {kind} | {}",
+                public_key.as_deref().unwrap_or("(no public key)")
+            ),
+            EntryData::ApiToken {
+                token,
+                scopes,
+                expires,
+            } => {
+                let mut text = format!("{head} | {kind} | {}", &**token);
+                if !scopes.is_empty() {
+                    text += &format!(" | scopes {}", scopes.join(","));
+                }
+                if let Some(expires) = expires {
+                    text += &format!(" | expires {expires}");
+                }
+                text
+            }
+            EntryData::Wifi {
+                ssid,
+                security,
+                password,
+            } => format!(
+                "{head} | {kind} | {ssid} | {security} | {}",
+                password.as_deref().unwrap_or("")
+            ),
+        }
+    }
+}
+
+/// `get`: the entry with the fields of `list`, its secrets replaced by
+/// `********` unless revealed.
+#[derive(Debug)]
+pub struct Shown {
+    entry: Vault,
+}
+
+impl Shown {
+    pub fn new(entry: &Vault, reveal: bool) -> Self {
+        let mut entry = entry.clone();
+        entry.history.clear();
+        if !reveal {
+            let hidden = || SecretString::new("********".to_owned());
+            match &mut entry.data {
+                EntryData::Login { password, .. } => *password = hidden(),
+                EntryData::SecureNote { text } => *text = hidden(),
+                EntryData::Card { number, cvv, .. } => {
+                    *number = hidden();
+                    *cvv = cvv.as_ref().map(|_| hidden());
+                }
+                EntryData::SshKey {
+                    private_key,
+                    passphrase,
+                    ..
+                } => {
+                    *private_key = hidden();
+                    *passphrase = passphrase.as_ref().map(|_| hidden());
+                }
+                EntryData::ApiToken { token, .. } => *token = hidden(),
+                EntryData::Wifi { password, .. } => {
+                    *password = password.as_ref().map(|_| hidden());
+                }
+            }
+            for field in entry.fields.iter_mut().filter(|f| f.secret) {
+                field.value = hidden();
+            }
+            entry.notes = entry.notes.as_ref().map(|_| hidden());
+        }
+        Self { entry }
+    }
+
+    /// One value by name: one of the entry's type's such as `password` or
+    /// `number`, an entry field such as `urls`, or else a custom field. Lists
+    /// come one item per line.
+    pub fn field(&self, name: &str) -> anyhow::Result<Option<Zeroizing<String>>> {
+        let mut value = serde_json::to_value(Entry::from(&self.entry))?;
+        let found = value["details"]
+            .get(name)
+            .filter(|_| name != "type")
+            .or_else(|| {
+                value
+                    .get(name)
+                    .filter(|_| !["details", "fields"].contains(&name))
+            })
+            .or_else(|| {
+                value["fields"]
+                    .as_array()?
+                    .iter()
+                    .find(|f| f["name"] == name)
+                    .map(|f| &f["value"])
+            })
+            .filter(|v| !v.is_null())
+            .map(|v| Zeroizing::new(plain(v, "\n")));
+        secret::wipe_json(&mut value);
+        Ok(found)
+    }
+}
+
+/// A value as text, list items separated by `separator`.
+fn plain(value: &serde_json::Value, separator: &str) -> String {
+    use serde_json::Value;
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| plain(item, separator))
+            .collect::<Vec<_>>()
+            .join(separator),
+        other => other.to_string(),
+    }
+}
+
+impl Serialize for Shown {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Entry::from(&self.entry).serialize(serializer)
+    }
+}
+
+impl Report for Shown {
+    const FIELDS: &'static [&'static str] = Entry::FIELDS;
+
+    /// One `name: value` line per field that is set, the type's own values
+    /// after the entry's name and type.
+    fn table(&self) -> String {
+        let e = &self.entry;
+        let mut lines = vec![
+            format!("id: {}", e.id),
+            format!("service: {}", e.service),
+            format!("type: {}", e.data.type_name()),
+        ];
+        let mut line = |name: &str, value: Option<&str>| {
+            if let Some(value) = value.filter(|v| !v.is_empty()) {
+                lines.push(format!("{name}: {value}"));
+            }
+        };
+        match &e.data {
+            EntryData::Login { username, password } => {
+                line("username", Some(username));
+                line("password", Some(password));
+            }
+            EntryData::SecureNote { text } => line("text", Some(text)),
+            EntryData::Card {
+                holder,
+                number,
+                expiry,
+                cvv,
+            } => {
+                line("holder", Some(holder));
+                line("number", Some(number));
+                line("expiry", Some(&expiry.to_string()));
+                line("cvv", cvv.as_deref());
+            }
+            EntryData::SshKey {
+                private_key,
+                public_key,
+                passphrase,
+            } => {
+                line("public_key", public_key.as_deref());
+                line("private_key", Some(private_key));
+                line("passphrase", passphrase.as_deref());
+            }
+            EntryData::ApiToken {
+                token,
+                scopes,
+                expires,
+            } => {
+                line("token", Some(token));
+                line("scopes", Some(&scopes.join(", ")));
+                line("expires", expires.map(|d| d.to_string()).as_deref());
+            }
+            EntryData::Wifi {
+                ssid,
+                security,
+                password,
+            } => {
+                line("ssid", Some(ssid));
+                line("security", Some(&security.to_string()));
+                line("password", password.as_deref());
+            }
+        }
+        line("urls", Some(&e.urls.join(", ")));
+        line("tags", Some(&e.tags.join(", ")));
+        line("notes", e.notes.as_deref());
+        for field in &e.fields {
+            line(&field.name, Some(&field.value));
+        }
+        let utc = |ms: Option<u64>| ms.map(|ms| timestamp::format_utc(ms / 1000));
+        line("created", utc(e.created_ms).as_deref());
+        line("modified", utc(e.modified_ms).as_deref());
+        line("last_used", utc(e.last_used_ms).as_deref());
+        lines.join("\n")
+    }
+}
+
+/// `init`.
+#[derive(Debug, Serialize)]
+pub struct Created {
+    pub path: String,
+    pub vault_id: String,
+}
+
+impl Report for Created {
+    const FIELDS: &'static [&'static str] = &["path", "vault_id"];
+
+    fn table(&self) -> String {
+        format!("Created vault at {}", self.path)
+    }
+}
+
+/// `passwd`.
+#[derive(Debug, Serialize)]
+pub struct PasswordChanged {
+    /// The key slot whose password changed.
+    pub slot_id: String,
+    /// The other key slots, which wrapped the replaced data key.
+    pub revoked_slots: Vec<String>,
+}
+
+impl Report for PasswordChanged {
+    const FIELDS: &'static [&'static str] = &["slot_id", "revoked_slots"];
+
+    fn table(&self) -> String {
+        if self.revoked_slots.is_empty() {
+            "Master password changed".to_owned()
+        } else {
+            format!(
+                "Master password changed. Revoked the {} other key slot(s), which held the old data key; add them again with `lockbox slot add` or `lockbox emergency-kit`.",
+                self.revoked_slots.len()
+            )
+        }
+    }
+}
+
+/// `add`.
+#[derive(Debug, Serialize)]
+pub struct Added {
+    pub id: usize,
+    /// The length of the password if `--generate` made it, otherwise absent.
+    pub generated_length: Option<usize>,
+    /// As in `list`.
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+}
+
+impl Report for Added {
+    const FIELDS: &'static [&'static str] = &["id", "generated_length", "type"];
+
+    fn table(&self) -> String {
+        let mut text = format!("Added Entry with ID: {}", self.id);
+        if let Some(length) = self.generated_length {
+            text += &format!("\nGenerated a {length}-character password");
+        }
+        text
+    }
+}
+
+/// `remove`. An id that matches no entry is not an error.
+#[derive(Debug, Serialize)]
+pub struct Removed {
+    pub id: usize,
+    pub removed: bool,
+}
+
+impl Report for Removed {
+    const FIELDS: &'static [&'static str] = &["id", "removed"];
+
+    fn table(&self) -> String {
+        if self.removed {
+            format!("Removed Service with ID: {}", self.id)
+        } else {
+            format!("Unable to find service with the ID: {}", self.id)
+        }
+    }
+}
+
+/// `edit`.
+#[derive(Debug, Serialize)]
+pub struct Edited {
+    pub id: usize,
+    pub changed: bool,
+}
+
+impl Report for Edited {
+    const FIELDS: &'static [&'static str] = &["id", "changed"];
+
+    fn table(&self) -> String {
+        if self.changed {
+            format!("Updated entry {}", self.id)
+        } else {
+            format!("Entry {} is unchanged", self.id)
+        }
+    }
+}
+
+/// `migrate-plaintext`.
+#[derive(Debug, Serialize)]
+pub struct Migrated {
+    pub path: String,
+    pub entries: usize,
+}
+
+impl Report for Migrated {
+    const FIELDS: &'static [&'static str] = &["path", "entries"];
+
+    fn table(&self) -> String {
+        format!("Encrypted {} with {} entries", self.path, self.entries)
+    }
+}
+
+/// `backup list`: one per backup, newest first.
+#[derive(Debug, Serialize)]
+pub struct BackupInfo {
+    /// The id to pass to `backup restore`.
+    pub id: String,
+    /// When the backup was taken, as `YYYY-MM-DD HH:MM:SS UTC`.
+    pub taken: String,
+    /// Entries in the backup; absent if it does not open with the current
+    /// credentials.
+    pub entries: Option<usize>,
+}
+
+impl Report for BackupInfo {
+    const FIELDS: &'static [&'static str] = &["id", "taken", "entries"];
+
+    fn table(&self) -> String {
+        let entries = self.entries.map_or("?".to_owned(), |n| n.to_string());
+        format!("{} | {} | {entries} entries", self.id, self.taken)
+    }
+}
+
+/// `backup restore`.
+#[derive(Debug, Serialize)]
+pub struct Restored {
+    pub id: String,
+    pub taken: String,
+    pub entries: usize,
+}
+
+impl Report for Restored {
+    const FIELDS: &'static [&'static str] = &["id", "taken", "entries"];
+
+    fn table(&self) -> String {
+        format!(
+            "Restored backup {} from {} ({} entries)",
+            self.id, self.taken, self.entries
+        )
+    }
+}
+
+/// `backup policy`: the policy in effect after any change.
+impl Report for BackupPolicy {
+    const FIELDS: &'static [&'static str] = &["keep_last", "daily", "weekly"];
+
+    fn table(&self) -> String {
+        format!(
+            "keep_last: {}\ndaily: {}\nweekly: {}",
+            self.keep_last, self.daily, self.weekly
+        )
+    }
+}
+
+/// `kdf show`.
+#[derive(Debug, Serialize)]
+pub struct KdfInfo {
+    pub iterations: u32,
+    pub memory_kib: u32,
+    pub min_iterations: u32,
+    pub min_memory_kib: u32,
+}
+
+impl Report for KdfInfo {
+    const FIELDS: &'static [&'static str] = &[
+        "iterations",
+        "memory_kib",
+        "min_iterations",
+        "min_memory_kib",
+    ];
+
+    fn table(&self) -> String {
+        format!(
+            "iterations: {} (minimum {})\nmemory_kib: {} (minimum {})",
+            self.iterations, self.min_iterations, self.memory_kib, self.min_memory_kib
+        )
+    }
+}
+
+/// `kdf set`.
+#[derive(Debug, Serialize)]
+pub struct KdfSet {
+    pub iterations: u32,
+    pub memory_kib: u32,
+}
+
+impl Report for KdfSet {
+    const FIELDS: &'static [&'static str] = &["iterations", "memory_kib"];
+
+    fn table(&self) -> String {
+        format!(
+            "KDF parameters set to iterations={}, memory_kib={}",
+            self.iterations, self.memory_kib
+        )
+    }
+}
+
+/// `kdf calibrate`.
+#[derive(Debug, Serialize)]
+pub struct Calibrated {
+    pub iterations: u32,
+    pub memory_kib: u32,
+    /// How long the parameters took on this machine.
+    pub millis: u128,
+    /// Whether `--apply` re-wrapped the unlocking slot with them.
+    pub applied: bool,
+}
+
+impl Report for Calibrated {
+    const FIELDS: &'static [&'static str] = &["iterations", "memory_kib", "millis", "applied"];
+
+    fn table(&self) -> String {
+        let mut text = format!(
+            "iterations={}, memory_kib={} ({} ms on this machine)",
+            self.iterations, self.memory_kib, self.millis
+        );
+        if self.applied {
+            text += "\nKey slot re-wrapped with calibrated parameters";
+        }
+        text
+    }
+}
+
+/// `slot list`: one per key slot.
+#[derive(Debug, Serialize)]
+pub struct SlotInfo {
+    /// Whether this is the slot the vault was unlocked through.
+    pub unlocked: bool,
+    pub id: String,
+    /// `password`, `keyfile`, `password-keyfile`, `shamir` or `recovery-key`.
+    pub kind: SlotKind,
+    pub label: String,
+    /// When the slot was added, as `YYYY-MM-DD HH:MM:SS UTC`.
+    pub created: String,
+    pub kdf_iterations: u32,
+    pub kdf_memory_kib: u32,
+}
+
+impl Report for SlotInfo {
+    const FIELDS: &'static [&'static str] = &[
+        "unlocked",
+        "id",
+        "kind",
+        "label",
+        "created",
+        "kdf_iterations",
+        "kdf_memory_kib",
+    ];
+
+    fn table(&self) -> String {
+        format!(
+            "{} {} | {} | {} | {} | iterations={}, memory_kib={}",
+            if self.unlocked { "*" } else { " " },
+            self.id,
+            self.kind,
+            self.label,
+            self.created,
+            self.kdf_iterations,
+            self.kdf_memory_kib
+        )
+    }
+}
+
+/// `slot add` and `slot remove`.
+#[derive(Debug, Serialize)]
+pub struct SlotChanged {
+    pub id: String,
+    pub kind: SlotKind,
+    pub removed: bool,
+}
+
+impl Report for SlotChanged {
+    const FIELDS: &'static [&'static str] = &["id", "kind", "removed"];
+
+    fn table(&self) -> String {
+        if self.removed {
+            format!("Removed {} key slot {}", self.kind, self.id)
+        } else {
+            format!("Added key slot {}", self.id)
+        }
+    }
+}
+
+/// `recovery split`: one per share.
+#[derive(Debug, Serialize)]
+pub struct ShareInfo {
+    pub number: u8,
+    pub shares: u8,
+    pub threshold: u8,
+    /// The key slot the shares unlock.
+    pub slot_id: String,
+    /// The share in the chosen `--encoding`.
+    pub share: SecretString,
+}
+
+impl Report for ShareInfo {
+    const FIELDS: &'static [&'static str] = &["number", "shares", "threshold", "slot_id", "share"];
+
+    fn table(&self) -> String {
+        format!(
+            "Share {} of {}:\n{}\n",
+            self.number, self.shares, &*self.share
+        )
+    }
+}
+
+/// `recovery combine`.
+#[derive(Debug, Serialize)]
+pub struct Recovered {
+    /// The key slot the shares unlocked.
+    pub slot_id: String,
+    /// Every slot revoked, the recovery shares' included.
+    pub revoked_slots: Vec<String>,
+}
+
+impl Report for Recovered {
+    const FIELDS: &'static [&'static str] = &["slot_id", "revoked_slots"];
+
+    fn table(&self) -> String {
+        format!(
+            "Master password set. Revoked {} key slot(s), including these recovery shares; run `lockbox recovery split` to make new ones.",
+            self.revoked_slots.len()
+        )
+    }
+}
+
+/// `emergency-kit`. The table format prints the sheet itself unless it went
+/// to `--output`.
+#[derive(Debug, Serialize)]
+pub struct KitInfo {
+    pub vault_id: String,
+    /// The recovery key's slot.
+    pub slot_id: String,
+    /// The recovery key's words; absent when the kit went to `--output`.
+    pub recovery_key: Option<SecretString>,
+    /// Where the kit was written, if `--output` was given.
+    pub path: Option<String>,
+    /// Slots of earlier kits, revoked by this one.
+    pub revoked_slots: Vec<String>,
+    #[serde(skip)]
+    pub sheet: Option<SecretString>,
+}
+
+impl Report for KitInfo {
+    const FIELDS: &'static [&'static str] = &[
+        "vault_id",
+        "slot_id",
+        "recovery_key",
+        "path",
+        "revoked_slots",
+    ];
+
+    fn table(&self) -> String {
+        self.sheet.as_deref().unwrap_or_default().to_owned()
+    }
+}
+
+/// `history`: one per earlier value, newest first.
+#[derive(Debug, Serialize)]
+pub struct Version {
+    /// The entry's id.
+    pub id: usize,
+    /// The number to pass to `history restore`, 1 being the newest.
+    pub number: usize,
+    /// When the value was replaced, as `YYYY-MM-DD HH:MM:SS UTC`.
+    pub replaced: String,
+    /// As in `list`.
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// `********` where the value had a main secret, none of which is shown.
+    pub secret: Option<String>,
+}
+
+impl Version {
+    pub fn new(id: usize, number: usize, past: &PastData) -> Self {
+        Self {
+            id,
+            number,
+            replaced: timestamp::format_utc(past.replaced_ms / 1000),
+            kind: past.data.tag(),
+            secret: past.data.secret().map(|_| "********".to_owned()),
+        }
+    }
+}
+
+impl Report for Version {
+    const FIELDS: &'static [&'static str] = &["id", "number", "replaced", "type", "secret"];
+
+    fn table(&self) -> String {
+        format!(
+            "{} | {} | {} | {}",
+            self.number,
+            self.replaced,
+            self.kind,
+            self.secret.as_deref().unwrap_or("")
+        )
+    }
+}
+
+/// `history restore`.
+#[derive(Debug, Serialize)]
+pub struct VersionRestored {
+    pub id: usize,
+    /// The number the restored value had.
+    pub number: usize,
+}
+
+impl Report for VersionRestored {
+    const FIELDS: &'static [&'static str] = &["id", "number"];
+
+    fn table(&self) -> String {
+        format!(
+            "Restored earlier value {} of entry {}; the value it replaced is now number 1",
+            self.number, self.id
+        )
+    }
+}
+
+/// `history policy`: the policy in effect after any change.
+impl Report for HistoryPolicy {
+    const FIELDS: &'static [&'static str] = &["keep_last", "max_age_days"];
+
+    fn table(&self) -> String {
+        format!(
+            "keep_last: {}\nmax_age_days: {}",
+            self.keep_last, self.max_age_days
+        )
+    }
+}
+
+/// `keyfile generate`.
+#[derive(Debug, Serialize)]
+pub struct KeyfileWritten {
+    pub path: String,
+}
+
+impl Report for KeyfileWritten {
+    const FIELDS: &'static [&'static str] = &["path"];
+
+    fn table(&self) -> String {
+        format!("Wrote keyfile to {}", self.path)
+    }
+}
+
+/// `lock`.
+#[derive(Debug, Serialize)]
+pub struct AgentLocked {
+    /// False if no agent was running.
+    pub locked: bool,
+}
+
+impl Report for AgentLocked {
+    const FIELDS: &'static [&'static str] = &["locked"];
+
+    fn table(&self) -> String {
+        if self.locked {
+            "Agent locked".to_owned()
+        } else {
+            "No agent is running".to_owned()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_str<T: Report>(format: Format, items: &[T], list: bool) -> String {
+        String::from_utf8(render(format, items, list).unwrap().to_vec()).unwrap()
+    }
+
+    fn entries() -> Vec<Vault> {
+        let mut first = Vault::new(
+            1,
+            "a|b".into(),
+            "me\tyou".into(),
+            "p\\w\nx".to_owned().into(),
+        );
+        first.urls = vec![
+            "https://a.example/?q=1,2".into(),
+            "https://b.example".into(),
+        ];
+        first.tags = vec!["work".into()];
+        let mut second = Vault::new(2, "mail".into(), "".into(), "pw".to_owned().into());
+        second.created_ms = None;
+        second.modified_ms = None;
+        for entry in [&mut first, &mut second] {
+            entry.created_ms = entry.created_ms.map(|_| 86_400_000);
+            entry.modified_ms = entry.modified_ms.map(|_| 86_400_000);
+        }
+        vec![first, second]
+    }
+
+    #[test]
+    fn tsv_escapes_separators() {
+        let vaults = entries();
+        let rows = vaults.iter().map(Entry::from).collect::<Vec<_>>();
+
+        let tsv = render_str(Format::Tsv, &rows, true);
+        let lines = tsv
+            .lines()
+            .map(|l| l.split('\t').collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines[0], Entry::FIELDS);
+        assert_eq!(
+            lines[1][..11],
+            [
+                "1",
+                "a|b",
+                "me\\tyou",
+                "p\\\\w\\nx",
+                "https://a.example/?q=1\\,2,https://b.example",
+                "",
+                "work",
+                "",
+                "1970-01-02 00:00:00 UTC",
+                "1970-01-02 00:00:00 UTC",
+                "",
+            ]
+        );
+        assert_eq!(lines[1][11], "login");
+        assert_eq!(lines[2][..4], ["2", "mail", "", "pw"]);
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn json_lists_are_arrays_and_jsonl_is_one_object_per_line() {
+        let vaults = entries();
+        let rows = vaults.iter().map(Entry::from).collect::<Vec<_>>();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&render_str(Format::Json, &rows, true)).unwrap();
+        assert_eq!(json[0]["password"], "p\\w\nx");
+        assert_eq!(json.as_array().unwrap().len(), 2);
+
+        let none: [Entry; 0] = [];
+        assert_eq!(render_str(Format::Json, &none, true), "[]\n");
+
+        let lines = render_str(Format::Jsonl, &rows, true);
+        let lines = lines.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let second: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second["service"], "mail");
+    }
+
+    #[test]
+    fn single_results_are_objects_and_absent_fields_are_empty() {
+        let added = Added {
+            id: 3,
+            generated_length: None,
+            kind: "login",
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&render_str(
+            Format::Json,
+            std::slice::from_ref(&added),
+            false,
+        ))
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "id": 3, "generated_length": null, "type": "login" })
+        );
+        assert_eq!(
+            render_str(Format::Tsv, std::slice::from_ref(&added), false),
+            "id\tgenerated_length\ttype\n3\t\tlogin\n"
+        );
+        assert_eq!(
+            render_str(Format::Table, std::slice::from_ref(&added), false),
+            "Added Entry with ID: 3\n"
+        );
+    }
+
+    #[test]
+    fn versions_show_nothing_of_old_secrets() {
+        let past = |password: &str| PastData {
+            replaced_ms: 0,
+            data: EntryData::Login {
+                username: "me".into(),
+                password: password.to_owned().into(),
+            },
+        };
+        for password in ["correct horse battery", "hunter2", ""] {
+            let version = Version::new(1, 1, &past(password));
+            assert_eq!(version.secret.as_deref(), Some("********"));
+        }
+    }
+
+    #[test]
+    fn shown_entries_hide_secrets_unless_revealed() {
+        let mut entry = Vault::new(5, "bank".into(), "me".into(), "hunter22".to_owned().into());
+        entry.notes = Some("security answer: Rex".to_owned().into());
+        entry.fields = vec![
+            CustomField {
+                name: "pin".into(),
+                value: "1234".to_owned().into(),
+                secret: true,
+            },
+            CustomField {
+                name: "branch".into(),
+                value: "Main St".to_owned().into(),
+                secret: false,
+            },
+        ];
+
+        let masked = Shown::new(&entry, false);
+        let table = masked.table();
+        assert!(table.contains("password: ********\n"), "{table}");
+        assert!(table.contains("pin: ********\nbranch: Main St"), "{table}");
+        assert!(table.contains("notes: ********\n"), "{table}");
+        assert!(!table.contains("hunter22") && !table.contains("1234") && !table.contains("Rex"));
+
+        let revealed = Shown::new(&entry, true);
+        let field = |name| revealed.field(name).unwrap().map(|v| v.to_string());
+        assert_eq!(field("password").as_deref(), Some("hunter22"));
+        assert_eq!(field("pin").as_deref(), Some("1234"));
+        assert_eq!(field("service").as_deref(), Some("bank"));
+        assert_eq!(field("type").as_deref(), Some("login"));
+        assert_eq!(field("notes").as_deref(), Some("security answer: Rex"));
+        assert_eq!(field("details"), None);
+    }
+}
@@ -0,0 +1,165 @@
+//! Just enough of the Assuan protocol spoken by pinentry programs to ask for
+//! one secret.
+
+use lockbox::secret::SecretString;
+use std::{
+    env,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    process::{Command, Stdio},
+};
+use zeroize::Zeroizing;
+
+/// Asks for a secret through the pinentry program at `program`.
+pub fn get_pin(program: &Path, description: &str, prompt: &str) -> anyhow::Result<SecretString> {
+    let mut child = Command::new(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("unable to run {}: {e}", program.display()))?;
+    let mut writer = child.stdin.take().expect("piped");
+    let mut reader = BufReader::new(child.stdout.take().expect("piped"));
+    // Curses pinentries draw on the terminal named here, as with gpg-agent.
+    let tty = env::var("GPG_TTY").ok();
+    let pin = converse(
+        &mut reader,
+        &mut writer,
+        tty.as_deref(),
+        description,
+        prompt,
+    );
+    drop(writer);
+    child.wait()?;
+    pin
+}
+
+fn converse(
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+    tty: Option<&str>,
+    description: &str,
+    prompt: &str,
+) -> anyhow::Result<SecretString> {
+    let mut line = Zeroizing::new(String::new());
+    // The greeting.
+    response(reader, &mut line)?;
+    if let Some(tty) = tty {
+        request(reader, writer, &format!("OPTION ttyname={tty}"))?;
+    }
+    request(reader, writer, &format!("SETDESC {}", escape(description)))?;
+    request(reader, writer, &format!("SETPROMPT {}", escape(prompt)))?;
+
+    writeln!(writer, "GETPIN")?;
+    writer.flush()?;
+    let mut pin = Zeroizing::new(Vec::new());
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            anyhow::bail!("pinentry exited before answering");
+        }
+        let text = line.trim_end_matches(['\r', '\n']);
+        if let Some(data) = text.strip_prefix("D ") {
+            unescape_into(data, &mut pin)?;
+        } else if text == "OK" || text.starts_with("OK ") {
+            break;
+        } else if let Some(err) = text.strip_prefix("ERR ") {
+            anyhow::bail!(
+                "pinentry: {}",
+                err.split_once(' ').map_or(err, |(_, msg)| msg)
+            );
+        }
+    }
+    let _ = writeln!(writer, "BYE");
+
+    match String::from_utf8(std::mem::take(&mut *pin)) {
+        Ok(s) => Ok(SecretString::new(s)),
+        Err(e) => {
+            drop(Zeroizing::new(e.into_bytes()));
+            anyhow::bail!("pinentry returned invalid UTF-8")
+        }
+    }
+}
+
+/// Sends one command and waits for its `OK`.
+fn request(
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+    command: &str,
+) -> anyhow::Result<()> {
+    writeln!(writer, "{command}")?;
+    writer.flush()?;
+    let mut line = Zeroizing::new(String::new());
+    response(reader, &mut line)
+}
+
+/// Reads lines up to the `OK` or `ERR` that ends a response.
+fn response(reader: &mut impl BufRead, line: &mut String) -> anyhow::Result<()> {
+    loop {
+        line.clear();
+        if reader.read_line(line)? == 0 {
+            anyhow::bail!("pinentry exited before answering");
+        }
+        let text = line.trim_end_matches(['\r', '\n']);
+        if text == "OK" || text.starts_with("OK ") {
+            return Ok(());
+        }
+        if let Some(err) = text.strip_prefix("ERR ") {
+            anyhow::bail!("pinentry: {err}");
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\n', "%0A")
+        .replace('\r', "%0D")
+}
+
+fn unescape_into(data: &str, out: &mut Vec<u8>) -> anyhow::Result<()> {
+    let mut bytes = data.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex = [bytes.next(), bytes.next()];
+            let [Some(hi), Some(lo)] = hex else {
+                anyhow::bail!("pinentry sent a malformed escape");
+            };
+            let digit = |c: u8| char::from(c).to_digit(16);
+            let (Some(hi), Some(lo)) = (digit(hi), digit(lo)) else {
+                anyhow::bail!("pinentry sent a malformed escape");
+            };
+            out.push((hi * 16 + lo) as u8);
+        } else {
+            out.push(b);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn reads_the_pin_and_escapes_the_description() {
+        let mut reader =
+            Cursor::new("OK Pleased to meet you\nOK\nOK\nS PIN_REPEATED\nD pa%25ss%0Aword\nOK\n");
+        let mut writer = Vec::new();
+
+        let pin = converse(&mut reader, &mut writer, None, "100% sure\nnow", "PIN").unwrap();
+
+        assert_eq!(&*pin, "pa%ss\nword");
+        let sent = String::from_utf8(writer).unwrap();
+        assert!(sent.contains("SETDESC 100%25 sure%0Anow\n"), "{sent}");
+        assert!(sent.contains("GETPIN\n"));
+    }
+
+    #[test]
+    fn reports_cancellation() {
+        let mut reader = Cursor::new("OK\nOK\nOK\nERR 83886179 Operation cancelled <Pinentry>\n");
+
+        let err = converse(&mut reader, &mut Vec::new(), None, "d", "p").unwrap_err();
+
+        assert_eq!(err.to_string(), "pinentry: Operation cancelled <Pinentry>");
+    }
+}
@@ -0,0 +1,241 @@
+//! Recovery secrets: random keys that open their own key slots, written down
+//! either whole as a mnemonic or split with [`shamir`] so that no single
+//! holder can unlock the vault.
+//!
+//! A mnemonic is the secret followed by a checksum, one word per byte. A share
+//! is written down as `version || slot id || threshold || x || y ||
+//! checksum`, either as words or as base64. The slot id ties the shares of
+//! one split together and to the slot they open; the checksum catches typos
+//! before any key derivation is attempted.
+
+use crate::{
+    crypto,
+    shamir::{self, Share},
+    words,
+};
+use base64::{Engine, engine::general_purpose};
+use zeroize::Zeroizing;
+
+/// Length of the secret a recovery slot is opened with.
+pub const SECRET_LEN: usize = 32;
+const SHARE_VERSION: u8 = 1;
+const SLOT_ID_LEN: usize = 4;
+const CHECKSUM_LEN: usize = 2;
+const SHARE_LEN: usize = 1 + SLOT_ID_LEN + 1 + 1 + SECRET_LEN + CHECKSUM_LEN;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ShareEncoding {
+    /// One word per byte.
+    #[default]
+    Words,
+    /// Base64.
+    Text,
+}
+
+pub struct RecoveryShare {
+    pub slot_id: String,
+    pub threshold: u8,
+    share: Share,
+}
+
+impl RecoveryShare {
+    pub fn number(&self) -> u8 {
+        self.share.x
+    }
+
+    pub fn encode(&self, encoding: ShareEncoding) -> Zeroizing<String> {
+        let slot_id = crypto::unhex(&self.slot_id).expect("slot ids are hex");
+        let mut bytes = Zeroizing::new(Vec::with_capacity(SHARE_LEN));
+        bytes.push(SHARE_VERSION);
+        bytes.extend_from_slice(&slot_id);
+        bytes.push(self.threshold);
+        bytes.push(self.share.x);
+        bytes.extend_from_slice(&self.share.y);
+        let sum = checksum(&bytes);
+        bytes.extend_from_slice(&sum);
+        Zeroizing::new(match encoding {
+            ShareEncoding::Words => words::encode(&bytes),
+            ShareEncoding::Text => general_purpose::STANDARD.encode(&*bytes),
+        })
+    }
+
+    /// Parses a share in either encoding.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let bytes = Zeroizing::new(if text.split_whitespace().count() > 1 {
+            words::decode(text)?
+        } else {
+            general_purpose::STANDARD
+                .decode(text.trim())
+                .map_err(|_| "not a recovery share".to_owned())?
+        });
+        if bytes.len() != SHARE_LEN {
+            return Err(format!(
+                "a share has {SHARE_LEN} words; this one has {}",
+                bytes.len()
+            ));
+        }
+        let (body, sum) = bytes.split_at(SHARE_LEN - CHECKSUM_LEN);
+        if checksum(body) != sum {
+            return Err("checksum mismatch; check the share for typos".into());
+        }
+        if body[0] != SHARE_VERSION {
+            return Err(format!("unsupported share version {}", body[0]));
+        }
+        let slot_id = crypto::hex(&body[1..1 + SLOT_ID_LEN]);
+        let [threshold, x] = [body[1 + SLOT_ID_LEN], body[2 + SLOT_ID_LEN]];
+        if x == 0 || threshold < 2 {
+            return Err("malformed share".into());
+        }
+        Ok(Self {
+            slot_id,
+            threshold,
+            share: Share {
+                x,
+                y: Zeroizing::new(body[3 + SLOT_ID_LEN..].to_vec()),
+            },
+        })
+    }
+}
+
+/// Writes a recovery key as words, with a checksum.
+pub fn mnemonic(secret: &[u8]) -> Zeroizing<String> {
+    let mut bytes = Zeroizing::new(secret.to_vec());
+    bytes.extend_from_slice(&checksum(secret));
+    Zeroizing::new(words::encode(&bytes))
+}
+
+/// Reverses [`mnemonic`].
+pub fn parse_mnemonic(text: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+    let mut bytes = Zeroizing::new(words::decode(text)?);
+    if bytes.len() != SECRET_LEN + CHECKSUM_LEN {
+        return Err(format!(
+            "a recovery key has {} words; this one has {}",
+            SECRET_LEN + CHECKSUM_LEN,
+            bytes.len()
+        ));
+    }
+    let sum = bytes.split_off(SECRET_LEN);
+    if checksum(&bytes) != sum[..] {
+        return Err("checksum mismatch; check the recovery key for typos".into());
+    }
+    Ok(bytes)
+}
+
+/// A fresh secret for a recovery slot.
+pub fn new_secret() -> anyhow::Result<Zeroizing<Vec<u8>>> {
+    let mut secret = Zeroizing::new(vec![0u8; SECRET_LEN]);
+    orion::util::secure_rand_bytes(&mut secret)?;
+    Ok(secret)
+}
+
+/// Splits the secret of slot `slot_id` into `shares` shares.
+pub fn split(
+    slot_id: &str,
+    secret: &[u8],
+    threshold: u8,
+    shares: u8,
+) -> anyhow::Result<Vec<RecoveryShare>> {
+    if crypto::unhex(slot_id).map(|id| id.len()) != Some(SLOT_ID_LEN) {
+        anyhow::bail!("slot id {slot_id:?} cannot be encoded in a share");
+    }
+    Ok(shamir::split(secret, threshold, shares)?
+        .into_iter()
+        .map(|share| RecoveryShare {
+            slot_id: slot_id.to_owned(),
+            threshold,
+            share,
+        })
+        .collect())
+}
+
+/// Rebuilds the secret from at least a threshold of shares of one split.
+pub fn combine(shares: Vec<RecoveryShare>) -> anyhow::Result<Zeroizing<Vec<u8>>> {
+    let Some(first) = shares.first() else {
+        anyhow::bail!("no shares given");
+    };
+    if shares
+        .iter()
+        .any(|s| s.slot_id != first.slot_id || s.threshold != first.threshold)
+    {
+        anyhow::bail!("the shares come from different splits");
+    }
+    if shares.len() < usize::from(first.threshold) {
+        anyhow::bail!("{} of {} shares given", shares.len(), first.threshold);
+    }
+    let shares = shares.into_iter().map(|s| s.share).collect::<Vec<_>>();
+    shamir::combine(&shares)
+}
+
+fn checksum(bytes: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let digest = orion::hash::digest(bytes).expect("input is not empty");
+    let mut sum = [0u8; CHECKSUM_LEN];
+    sum.copy_from_slice(&digest.as_ref()[..CHECKSUM_LEN]);
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_round_trip_through_both_encodings() {
+        let secret = new_secret().unwrap();
+        let shares = split("0a1b2c3d", &secret, 2, 3).unwrap();
+
+        let parsed = [
+            RecoveryShare::parse(&shares[0].encode(ShareEncoding::Words)).unwrap(),
+            RecoveryShare::parse(&shares[2].encode(ShareEncoding::Text)).unwrap(),
+        ];
+
+        assert_eq!(parsed[0].slot_id, "0a1b2c3d");
+        assert_eq!(parsed[1].number(), 3);
+        assert_eq!(combine(parsed.into()).unwrap(), secret);
+    }
+
+    #[test]
+    fn checksum_catches_a_changed_word() {
+        let shares = split("0a1b2c3d", &new_secret().unwrap(), 2, 2).unwrap();
+        let text = shares[0].encode(ShareEncoding::Words);
+        let mut words = text.split(' ').collect::<Vec<_>>();
+        words[10] = if words[10] == "acorn" {
+            "alarm"
+        } else {
+            "acorn"
+        };
+
+        let err = RecoveryShare::parse(&words.join(" ")).err().unwrap();
+
+        assert!(err.contains("checksum"), "{err}");
+    }
+
+    #[test]
+    fn mnemonic_round_trips_and_checks_its_checksum() {
+        let secret = new_secret().unwrap();
+        let text = mnemonic(&secret);
+
+        assert_eq!(parse_mnemonic(&text).unwrap(), secret);
+
+        let mut words = text.split(' ').collect::<Vec<_>>();
+        words[0] = if words[0] == "acorn" {
+            "alarm"
+        } else {
+            "acorn"
+        };
+        let err = parse_mnemonic(&words.join(" ")).err().unwrap();
+        assert!(err.contains("checksum"), "{err}");
+    }
+
+    #[test]
+    fn refuses_too_few_or_mixed_shares() {
+        let secret = new_secret().unwrap();
+        let a = split("0a1b2c3d", &secret, 3, 3).unwrap();
+        let b = split("ffffffff", &secret, 3, 3).unwrap();
+
+        assert!(combine(a.into_iter().take(2).collect()).is_err());
+
+        let mut mixed = split("0a1b2c3d", &secret, 3, 3).unwrap();
+        mixed.truncate(2);
+        mixed.extend(b.into_iter().take(1));
+        assert!(combine(mixed).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+//! Memory that holds secrets: wiped on drop, kept out of swap where the
+//! platform allows, and never printed by `Debug`.
+//!
+//! Locking is best effort. Pages are not unlocked on drop, since a page can be
+//! shared with another secret that is still alive; they stay locked until the
+//! process exits.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, io, ops::Deref};
+use zeroize::Zeroize;
+
+/// A string that is wiped when dropped and redacted in `Debug`.
+#[derive(Default, PartialEq, Eq)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(s: String) -> Self {
+        lock(s.as_ptr(), s.capacity());
+        Self(s)
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(s: String) -> Self {
+        Self::new(s)
+    }
+}
+
+impl Deref for SecretString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Clone for SecretString {
+    fn clone(&self) -> Self {
+        Self::new(self.0.clone())
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::new)
+    }
+}
+
+/// A growable byte buffer that wipes every allocation it leaves behind, for
+/// plaintext being serialized or decrypted.
+#[derive(Default)]
+pub struct SecretBuf(Vec<u8>);
+
+impl SecretBuf {
+    pub fn zeroed(len: usize) -> Self {
+        let buf = vec![0; len];
+        lock(buf.as_ptr(), buf.capacity());
+        Self(buf)
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        let needed = self.0.len() + bytes.len();
+        if needed > self.0.capacity() {
+            // Grow by hand so the old allocation is wiped, not just freed.
+            let mut grown = Vec::with_capacity(needed.max(2 * self.0.capacity()).max(64));
+            lock(grown.as_ptr(), grown.capacity());
+            grown.extend_from_slice(&self.0);
+            self.0.zeroize();
+            self.0 = grown;
+        }
+        self.0.extend_from_slice(bytes);
+    }
+}
+
+impl Deref for SecretBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl io::Write for SecretBuf {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SecretBuf {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for SecretBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{} redacted bytes>", self.0.len())
+    }
+}
+
+/// Wipes every string in a JSON value, for plaintext read as untyped JSON.
+pub fn wipe_json(value: &mut serde_json::Value) {
+    use serde_json::Value;
+    match value {
+        Value::String(s) => s.zeroize(),
+        Value::Array(items) => items.iter_mut().for_each(wipe_json),
+        Value::Object(map) => map.values_mut().for_each(wipe_json),
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+/// Disables core dumps for this process, so a crash can't write secrets to
+/// disk.
+pub fn harden_process() {
+    #[cfg(unix)]
+    unsafe {
+        let none = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        libc::setrlimit(libc::RLIMIT_CORE, &none);
+    }
+    // Also keeps other processes of the same user from attaching with ptrace
+    // or reading /proc/<pid>/mem.
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0);
+    }
+}
+
+#[cfg(unix)]
+fn lock(ptr: *const u8, len: usize) {
+    if len > 0 {
+        unsafe {
+            libc::mlock(ptr.cast(), len);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn lock(_ptr: *const u8, _len: usize) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_is_redacted() {
+        let s = SecretString::from("hunter2".to_owned());
+        assert_eq!(format!("{s:?}"), "<redacted>");
+        assert_eq!(&*s, "hunter2");
+    }
+
+    #[test]
+    fn buffer_keeps_contents_across_growth() {
+        let mut buf = SecretBuf::default();
+        for i in 0..100u8 {
+            io::Write::write_all(&mut buf, &[i; 7]).unwrap();
+        }
+        assert_eq!(buf.len(), 700);
+        assert!(buf.chunks(7).enumerate().all(|(i, c)| c == [i as u8; 7]));
+    }
+}
@@ -0,0 +1,156 @@
+//! Shamir secret sharing over GF(2^8), byte by byte.
+//!
+//! Each byte of the secret is the constant term of its own random polynomial
+//! of degree `threshold - 1`; share `x` holds every polynomial evaluated at
+//! `x`. Any `threshold` shares rebuild the secret by Lagrange interpolation at
+//! zero, and fewer reveal nothing about it.
+
+use orion::util::secure_rand_bytes;
+use zeroize::Zeroizing;
+
+pub struct Share {
+    /// The evaluation point, never zero.
+    pub x: u8,
+    pub y: Zeroizing<Vec<u8>>,
+}
+
+/// Splits `secret` into `shares` shares, any `threshold` of which rebuild it.
+pub fn split(secret: &[u8], threshold: u8, shares: u8) -> anyhow::Result<Vec<Share>> {
+    if threshold < 2 || threshold > shares {
+        anyhow::bail!("need 2 <= threshold <= shares (got {threshold} of {shares})");
+    }
+    let mut out = (1..=shares)
+        .map(|x| Share {
+            x,
+            y: Zeroizing::new(Vec::with_capacity(secret.len())),
+        })
+        .collect::<Vec<_>>();
+    let mut coefficients = Zeroizing::new(vec![0u8; usize::from(threshold)]);
+    for &byte in secret {
+        coefficients[0] = byte;
+        secure_rand_bytes(&mut coefficients[1..])?;
+        for share in &mut out {
+            // Horner's rule, highest coefficient first.
+            let y = coefficients
+                .iter()
+                .rev()
+                .fold(0, |acc, &c| mul(acc, share.x) ^ c);
+            share.y.push(y);
+        }
+    }
+    Ok(out)
+}
+
+/// Rebuilds the secret from shares with distinct, non-zero `x`. With fewer
+/// than the threshold the result is simply wrong, so callers check it.
+pub fn combine(shares: &[Share]) -> anyhow::Result<Zeroizing<Vec<u8>>> {
+    let Some(first) = shares.first() else {
+        anyhow::bail!("no shares given");
+    };
+    let len = first.y.len();
+    for (i, share) in shares.iter().enumerate() {
+        if share.x == 0 || share.y.len() != len {
+            anyhow::bail!("malformed share");
+        }
+        if shares[..i].iter().any(|s| s.x == share.x) {
+            anyhow::bail!("share {} was given twice", share.x);
+        }
+    }
+
+    let mut secret = Zeroizing::new(vec![0u8; len]);
+    for (i, share) in shares.iter().enumerate() {
+        // Lagrange basis polynomial for this share, evaluated at zero. In
+        // GF(2^8) subtraction is XOR, so (0 - x_j) / (x_i - x_j) is
+        // x_j / (x_i ^ x_j).
+        let basis = shares
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .fold(1, |acc, (_, other)| {
+                mul(acc, mul(other.x, inverse(share.x ^ other.x)))
+            });
+        for (s, &y) in secret.iter_mut().zip(share.y.iter()) {
+            *s ^= mul(y, basis);
+        }
+    }
+    Ok(secret)
+}
+
+/// Multiplication modulo the AES polynomial x^8 + x^4 + x^3 + x + 1, without
+/// data-dependent branches.
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+    product
+}
+
+/// `a^254`, which is `a^-1` for every non-zero `a`.
+fn inverse(a: u8) -> u8 {
+    let mut result = 1;
+    for _ in 0..254 {
+        result = mul(result, a);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverse_undoes_multiplication() {
+        for a in 1..=255 {
+            assert_eq!(mul(a, inverse(a)), 1, "{a}");
+        }
+    }
+
+    #[test]
+    fn any_threshold_subset_rebuilds_the_secret() {
+        let secret = b"correct horse battery staple!!!!";
+        let shares = split(secret, 3, 5).unwrap();
+
+        for skip in 0..5 {
+            for other in 0..5 {
+                let subset = shares
+                    .iter()
+                    .enumerate()
+                    .filter(|&(i, _)| i != skip && i != other)
+                    .take(3)
+                    .map(|(_, s)| Share {
+                        x: s.x,
+                        y: s.y.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                assert_eq!(&combine(&subset).unwrap()[..], secret);
+            }
+        }
+    }
+
+    #[test]
+    fn too_few_shares_do_not_rebuild_the_secret() {
+        let secret = [7u8; 32];
+        let shares = split(&secret, 3, 5).unwrap();
+
+        let two = shares.into_iter().take(2).collect::<Vec<_>>();
+
+        assert_ne!(&combine(&two).unwrap()[..], secret);
+    }
+
+    #[test]
+    fn rejects_bad_parameters_and_duplicates() {
+        assert!(split(b"s", 1, 3).is_err());
+        assert!(split(b"s", 4, 3).is_err());
+
+        let shares = split(b"s", 2, 2).unwrap();
+        let dup = [&shares[0], &shares[0]].map(|s| Share {
+            x: s.x,
+            y: s.y.clone(),
+        });
+        assert!(combine(&dup).is_err());
+    }
+}
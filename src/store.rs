@@ -0,0 +1,514 @@
+//! The decrypted contents of a vault and how they are sealed into, and opened
+//! from, an [`EncryptedFile`].
+
+use crate::{
+    atomic,
+    backup::{self, BackupPolicy},
+    crypto,
+    entry::{DataFields, EntryData},
+    error::{Error, LoadError},
+    format::{self, EncryptedFile, Header, VaultDocument},
+    history::{HistoryPolicy, PastData},
+    keyring::{Credential, Keyring},
+    secret::{self, SecretBuf, SecretString},
+    timestamp,
+};
+use anyhow::Context;
+use base64::{Engine, engine::general_purpose};
+use serde::{
+    Deserialize, Deserializer, Serialize,
+    de::{self, IgnoredAny, MapAccess, Visitor},
+};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+/// One entry of a vault. Everything after the password was added later and
+/// defaults to empty, so entries from older vaults load unchanged.
+#[derive(Debug, Clone, Serialize)]
+pub struct Vault {
+    pub id: usize,
+    /// The name the entry is listed under.
+    pub service: String,
+    #[serde(flatten)]
+    pub data: EntryData,
+    /// Login pages the entry is for.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub urls: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<SecretString>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<CustomField>,
+    /// Milliseconds since the epoch; absent for entries made before these
+    /// were recorded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used_ms: Option<u64>,
+    /// Earlier values of `data`, newest first.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<PastData>,
+}
+
+// The entry's own fields and its type's values are stored side by side, and
+// read as they come without buffering the map; see `DataFields`.
+impl<'de> Deserialize<'de> for Vault {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        struct VaultVisitor;
+
+        impl<'de> Visitor<'de> for VaultVisitor {
+            type Value = Vault;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a vault entry")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Vault, A::Error> {
+                let mut id = None;
+                let mut service = None;
+                let mut data = DataFields::default();
+                let mut urls = None;
+                let mut notes = None;
+                let mut tags = None;
+                let mut fields = None;
+                let mut created_ms = None;
+                let mut modified_ms = None;
+                let mut last_used_ms = None;
+                let mut history = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "id" => id = Some(map.next_value()?),
+                        "service" => service = Some(map.next_value()?),
+                        "urls" => urls = map.next_value()?,
+                        "notes" => notes = map.next_value()?,
+                        "tags" => tags = map.next_value()?,
+                        "fields" => fields = map.next_value()?,
+                        "created_ms" => created_ms = map.next_value()?,
+                        "modified_ms" => modified_ms = map.next_value()?,
+                        "last_used_ms" => last_used_ms = map.next_value()?,
+                        "history" => history = map.next_value()?,
+                        other => {
+                            if !data.read(other, &mut map)? {
+                                map.next_value::<IgnoredAny>()?;
+                            }
+                        }
+                    }
+                }
+                Ok(Vault {
+                    id: id.ok_or_else(|| de::Error::missing_field("id"))?,
+                    service: service.ok_or_else(|| de::Error::missing_field("service"))?,
+                    data: data.finish()?,
+                    urls: urls.unwrap_or_default(),
+                    notes,
+                    tags: tags.unwrap_or_default(),
+                    fields: fields.unwrap_or_default(),
+                    created_ms,
+                    modified_ms,
+                    last_used_ms,
+                    history: history.unwrap_or_default(),
+                })
+            }
+        }
+
+        d.deserialize_map(VaultVisitor)
+    }
+}
+
+impl Vault {
+    /// A login.
+    pub fn new(id: usize, service: String, username: String, password: SecretString) -> Self {
+        Self::with_data(id, service, EntryData::Login { username, password })
+    }
+
+    pub fn with_data(id: usize, service: String, data: EntryData) -> Self {
+        let now = timestamp::now_millis();
+        Self {
+            id,
+            service,
+            data,
+            urls: Vec::new(),
+            notes: None,
+            tags: Vec::new(),
+            fields: Vec::new(),
+            created_ms: Some(now),
+            modified_ms: Some(now),
+            last_used_ms: None,
+            history: Vec::new(),
+        }
+    }
+
+    /// The username of a login.
+    pub fn username(&self) -> Option<&str> {
+        match &self.data {
+            EntryData::Login { username, .. } => Some(username),
+            _ => None,
+        }
+    }
+
+    /// The password of a login.
+    pub fn password(&self) -> Option<&SecretString> {
+        match &self.data {
+            EntryData::Login { password, .. } => Some(password),
+            _ => None,
+        }
+    }
+
+    /// Replaces the entry's values, keeping the old ones in its history as far
+    /// as `policy` allows. Setting the same values again changes nothing.
+    pub fn replace_data(&mut self, data: EntryData, policy: &HistoryPolicy) {
+        if data == self.data {
+            return;
+        }
+        let now = timestamp::now_millis();
+        let old = std::mem::replace(&mut self.data, data);
+        self.history.insert(
+            0,
+            PastData {
+                replaced_ms: now,
+                data: old,
+            },
+        );
+        policy.prune(&mut self.history, now);
+        self.modified_ms = Some(now);
+    }
+
+    /// Brings back the value at `index` in the history, 0 being the most
+    /// recent; the current value takes its place in the history. `None` if
+    /// there is no such value.
+    pub fn restore_version(&mut self, index: usize, policy: &HistoryPolicy) -> Option<()> {
+        if index >= self.history.len() {
+            return None;
+        }
+        let past = self.history.remove(index);
+        self.replace_data(past.data, policy);
+        Some(())
+    }
+
+    /// Checks the name and custom fields, and the values against what their
+    /// type allows.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.service.trim().is_empty() {
+            return Err(Error::Invalid("the name is empty".into()));
+        }
+        for (i, field) in self.fields.iter().enumerate() {
+            if field.name.trim().is_empty() {
+                return Err(Error::Invalid("a custom field has no name".into()));
+            }
+            if self.fields[..i].iter().any(|f| f.name == field.name) {
+                return Err(Error::Invalid(format!(
+                    "two custom fields are named \"{}\"",
+                    field.name
+                )));
+            }
+        }
+        self.data.validate()
+    }
+
+    /// Takes the name, values and details of `edited`, keeping this entry's
+    /// id, timestamps and history; old values join the history as far as
+    /// `policy` allows. Returns whether anything changed, which is only then
+    /// recorded as a modification.
+    pub fn update_from(&mut self, edited: Vault, policy: &HistoryPolicy) -> bool {
+        let details_changed = !self.same_details(&edited);
+        let data_changed = edited.data != self.data;
+        let Vault {
+            service,
+            data,
+            urls,
+            notes,
+            tags,
+            fields,
+            ..
+        } = edited;
+        self.service = service;
+        self.urls = urls;
+        self.notes = notes;
+        self.tags = tags;
+        self.fields = fields;
+        self.replace_data(data, policy);
+        if details_changed {
+            self.touch();
+        }
+        details_changed || data_changed
+    }
+
+    /// Whether `other` has the same name, URLs, notes, tags and custom fields.
+    pub fn same_details(&self, other: &Vault) -> bool {
+        self.service == other.service
+            && self.urls == other.urls
+            && self.notes == other.notes
+            && self.tags == other.tags
+            && self.fields == other.fields
+    }
+
+    /// Records that the entry was just changed.
+    pub fn touch(&mut self) {
+        self.modified_ms = Some(timestamp::now_millis());
+    }
+
+    /// Records that a secret of the entry was just read. Reading changes
+    /// nothing, so the modified time stays.
+    pub fn mark_used(&mut self) {
+        self.last_used_ms = Some(timestamp::now_millis());
+    }
+
+    /// Adds `tag` unless the entry already has it.
+    pub fn add_tag(&mut self, tag: &str) {
+        if !self.tags.iter().any(|t| t == tag) {
+            self.tags.push(tag.to_owned());
+        }
+    }
+}
+
+/// A named value of an entry beyond its username and password, such as a PIN
+/// or a security question.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CustomField {
+    pub name: String,
+    pub value: SecretString,
+    /// Hidden like the password when the entry is shown.
+    #[serde(default)]
+    pub secret: bool,
+}
+
+/// Vault-wide preferences, stored encrypted alongside the entries.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub backup: BackupPolicy,
+    pub history: HistoryPolicy,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Store {
+    pub next_id: usize,
+    pub vault_items: Vec<Vault>,
+    #[serde(default)]
+    pub settings: Settings,
+}
+
+impl Store {
+    pub fn new() -> Self {
+        Store {
+            next_id: 1,
+            vault_items: vec![],
+            settings: Settings::default(),
+        }
+    }
+
+    pub fn load(path: &Path, credential: &Credential) -> Result<(Self, Keyring), LoadError> {
+        if !path.exists() {
+            return Err(LoadError::NotFound(path.to_path_buf()));
+        }
+        let bytes = fs::read(path).map_err(LoadError::Io)?;
+        Self::decrypt(&bytes, credential)
+    }
+
+    pub fn decrypt(bytes: &[u8], credential: &Credential) -> Result<(Self, Keyring), LoadError> {
+        match format::parse(bytes)? {
+            VaultDocument::Encrypted(enc) => decrypt_store(&enc, credential),
+            VaultDocument::LegacyPlaintext(mut value) => {
+                secret::wipe_json(&mut value);
+                Err(LoadError::Plaintext)
+            }
+        }
+    }
+
+    /// Encrypts the store and writes it over `path`, keeping a backup of the
+    /// previous file. Returns the temporary files of interrupted saves that
+    /// were cleared away first.
+    pub fn save(&self, path: &Path, keyring: &Keyring) -> Result<Vec<PathBuf>, Error> {
+        self.write(path, keyring, true)
+    }
+
+    /// Like [`save`](Self::save), but keeps no backup. For changes too slight
+    /// to restore, such as a recorded use, which would otherwise push real
+    /// changes out of the backups kept.
+    pub fn save_without_backup(
+        &self,
+        path: &Path,
+        keyring: &Keyring,
+    ) -> Result<Vec<PathBuf>, Error> {
+        self.write(path, keyring, false)
+    }
+
+    fn write(&self, path: &Path, keyring: &Keyring, backup: bool) -> Result<Vec<PathBuf>, Error> {
+        let enc = encrypt_store(self, keyring).map_err(Error::Other)?;
+        let json = serde_json::to_vec_pretty(&enc).expect("serialize_error");
+
+        let stale = atomic::remove_stale_temp_files(path)?;
+        if backup {
+            backup::snapshot(path, &self.settings.backup, timestamp::now_millis())?;
+        }
+        atomic::write(path, &json)?;
+        Ok(stale)
+    }
+}
+
+pub fn encrypt_store(store: &Store, keyring: &Keyring) -> anyhow::Result<EncryptedFile> {
+    if keyring.slots().is_empty() {
+        anyhow::bail!("vault has no key slots; it could never be opened again");
+    }
+    let header = Header {
+        format_version: format::FORMAT_VERSION,
+        vault_id: keyring.vault_id().to_owned(),
+        cipher: format::CIPHER_XCHACHA20_POLY1305.into(),
+        key_slots: keyring.slots().to_vec(),
+    };
+    let ad = header.associated_data();
+
+    let mut plaintext = SecretBuf::default();
+    serde_json::to_writer(&mut plaintext, store).context("serialize_store")?;
+
+    let blob =
+        crypto::seal(keyring.data_key(), &plaintext, Some(&ad)).context("encryption_failed")?;
+
+    Ok(EncryptedFile {
+        header,
+        blob_b64: general_purpose::STANDARD.encode(&blob),
+        associated_data: Some(ad),
+    })
+}
+
+pub fn decrypt_store(
+    enc: &EncryptedFile,
+    credential: &Credential,
+) -> Result<(Store, Keyring), LoadError> {
+    let header = &enc.header;
+    let blob = general_purpose::STANDARD
+        .decode(&enc.blob_b64)
+        .map_err(|e| LoadError::Corrupt(format!("decode_blob: {e}")))?;
+
+    let keyring = Keyring::unlock(
+        header.vault_id.clone(),
+        header.key_slots.clone(),
+        credential,
+    )?;
+
+    // Once a slot has confirmed the key, a failure to open the blob can only
+    // mean the header or ciphertext were modified. Tampering with a slot's
+    // salt, KDF fields or key check changes or rejects its key, which is
+    // indistinguishable from a wrong password.
+    let plaintext = crypto::open(keyring.data_key(), &blob, enc.associated_data.as_deref())
+        .map_err(|_| {
+            if keyring.verified() {
+                LoadError::Integrity("header or ciphertext was modified".into())
+            } else {
+                LoadError::WrongPassword
+            }
+        })?;
+
+    let store: Store = serde_json::from_slice(&plaintext)
+        .map_err(|e| LoadError::Corrupt(format!("deserialize_store: {e}")))?;
+    Ok((store, keyring))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::WifiSecurity;
+
+    fn login(service: &str, password: &str) -> Vault {
+        Vault::new(1, service.into(), "me".into(), password.to_owned().into())
+    }
+
+    #[test]
+    fn updates_keep_the_id_and_creation_time_and_only_note_real_changes() {
+        let policy = HistoryPolicy::default();
+        let mut entry = login("mail", "old");
+        entry.created_ms = Some(5);
+        entry.modified_ms = Some(5);
+
+        let mut same = login("mail", "old");
+        same.id = 9;
+        assert!(!entry.update_from(same, &policy));
+        assert_eq!(entry.modified_ms, Some(5));
+
+        let mut renamed = login("webmail", "old");
+        renamed.tags = vec!["work".into()];
+        assert!(entry.update_from(renamed, &policy));
+        assert_eq!((entry.id, entry.created_ms), (1, Some(5)));
+        assert_eq!(
+            (entry.service.as_str(), &entry.tags[..]),
+            ("webmail", &["work".to_owned()][..])
+        );
+        assert!(entry.modified_ms > Some(5) && entry.history.is_empty());
+
+        assert!(entry.update_from(login("webmail", "new"), &policy));
+        assert_eq!(entry.password().map(|p| &**p), Some("new"));
+        assert_eq!(entry.history.len(), 1);
+        assert!(entry.tags.is_empty());
+    }
+
+    #[test]
+    fn using_an_entry_is_not_a_change() {
+        let mut entry = login("mail", "pw");
+        entry.modified_ms = Some(5);
+        assert_eq!(entry.last_used_ms, None);
+        entry.mark_used();
+        assert!(entry.last_used_ms > Some(5));
+        assert_eq!(entry.modified_ms, Some(5));
+        assert!(entry.history.is_empty());
+    }
+
+    #[test]
+    fn custom_field_names_are_unique() {
+        let mut entry = login("mail", "pw");
+        let field = CustomField {
+            name: "pin".into(),
+            value: "1".to_owned().into(),
+            secret: true,
+        };
+        entry.fields = vec![field.clone()];
+        assert!(entry.validate().is_ok());
+        entry.fields.push(field);
+        assert!(matches!(entry.validate(), Err(Error::Invalid(_))));
+        entry.fields.clear();
+        entry.service = " ".into();
+        assert!(matches!(entry.validate(), Err(Error::Invalid(_))));
+    }
+
+    #[test]
+    fn entries_from_older_releases_load_with_empty_details() {
+        let json = r#"{"id":4,"service":"mail","username":"me","password":"pw"}"#;
+
+        let entry: Vault = serde_json::from_str(json).unwrap();
+
+        assert_eq!(entry.username(), Some("me"));
+        assert_eq!(entry.password().map(|p| &**p), Some("pw"));
+        assert!(entry.urls.is_empty() && entry.tags.is_empty() && entry.fields.is_empty());
+        assert!(entry.notes.is_none() && entry.created_ms.is_none());
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            r#"{"id":4,"service":"mail","type":"login","username":"me","password":"pw"}"#
+        );
+    }
+
+    #[test]
+    fn typed_entries_load_whatever_the_order_of_their_keys() {
+        let json = r#"{"password":null,"security":"wpa2","history":[{"replaced_ms":1,
+            "data":{"password":"old","ssid":"home","security":"wpa2","type":"wifi"}}],
+            "ssid":"home","service":"wifi","type":"wifi","id":2,"extra":[1]}"#;
+
+        let entry: Vault = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            entry.data,
+            EntryData::Wifi {
+                ssid: "home".into(),
+                security: WifiSecurity::Wpa2,
+                password: None,
+            }
+        );
+        assert_eq!(entry.history[0].data.secret().map(|p| &**p), Some("old"));
+        let error = |json: &str| serde_json::from_str::<Vault>(json).unwrap_err().to_string();
+        assert!(error(r#"{"id":1,"service":"a","type":"card","holder":"me"}"#).contains("number"));
+        assert!(error(r#"{"id":1,"service":"a","type":"phone"}"#).contains("unknown variant"));
+        assert!(error(r#"{"id":1,"username":"me","password":"pw"}"#).contains("service"));
+    }
+}
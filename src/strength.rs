@@ -0,0 +1,86 @@
+//! Rough strength estimate for new master passwords.
+//!
+//! This is deliberately simple: it sizes the character pool from the classes a
+//! password uses, discounts characters that repeat or continue a run from the
+//! previous one, and rejects a short list of very common passwords outright.
+
+/// Minimum estimated entropy accepted for a master password.
+pub const MIN_ENTROPY_BITS: f64 = 60.0;
+
+const COMMON: &[&str] = &[
+    "password",
+    "passw0rd",
+    "123456",
+    "12345678",
+    "123456789",
+    "qwerty",
+    "qwertyuiop",
+    "letmein",
+    "iloveyou",
+    "admin",
+    "welcome",
+    "monkey",
+    "dragon",
+    "lockbox",
+    "correcthorsebatterystaple",
+];
+
+/// Estimated entropy of `password` in bits.
+pub fn entropy_bits(password: &str) -> f64 {
+    let lower = password.to_lowercase();
+    if COMMON.iter().any(|c| lower == *c) {
+        return 0.0;
+    }
+
+    let (mut has_lower, mut has_upper, mut has_digit, mut has_symbol, mut has_other) =
+        (false, false, false, false, false);
+    for c in password.chars() {
+        match c {
+            'a'..='z' => has_lower = true,
+            'A'..='Z' => has_upper = true,
+            '0'..='9' => has_digit = true,
+            c if c.is_ascii() => has_symbol = true,
+            _ => has_other = true,
+        }
+    }
+    let pool = [
+        (has_lower, 26),
+        (has_upper, 26),
+        (has_digit, 10),
+        (has_symbol, 33),
+        (has_other, 100),
+    ]
+    .iter()
+    .filter(|(present, _)| *present)
+    .map(|(_, size)| size)
+    .sum::<u32>();
+    if pool == 0 {
+        return 0.0;
+    }
+
+    // Characters that repeat or step by one from their predecessor ("aaa",
+    // "abc", "321") add very little to a guesser's search space.
+    let mut effective = 0.0;
+    let mut prev: Option<char> = None;
+    for c in password.chars() {
+        effective += match prev {
+            Some(p) if (p as i64 - c as i64).abs() <= 1 => 0.25,
+            _ => 1.0,
+        };
+        prev = Some(c);
+    }
+
+    effective * f64::from(pool).log2()
+}
+
+/// Returns an explanation when `password` is too weak to protect a vault.
+pub fn check(password: &str) -> Result<(), String> {
+    let bits = entropy_bits(password);
+    if bits < MIN_ENTROPY_BITS {
+        return Err(format!(
+            "master password is too weak (estimated {bits:.0} bits, need at least {MIN_ENTROPY_BITS:.0}); \
+             use a longer password or a passphrase of several random words"
+        ));
+    }
+    Ok(())
+}
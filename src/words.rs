@@ -0,0 +1,80 @@
+//! A list of 256 short words for writing binary secrets down by hand.
+//!
+//! Each byte becomes one word. No two words share their first four letters,
+//! so a word can be entered by its prefix and typos are easy to spot.
+
+const WORDS: [&str; 256] = [
+    "acorn", "alarm", "album", "amber", "angle", "apple", "armor", "arrow", "atlas", "bacon",
+    "badge", "bagel", "baker", "bamboo", "banjo", "barn", "basil", "beach", "beard", "berry",
+    "bison", "blade", "bloom", "board", "brick", "broom", "brush", "bucket", "cabin", "cable",
+    "cactus", "camel", "canoe", "canvas", "cargo", "carpet", "cedar", "chalk", "cheese", "cherry",
+    "chess", "cliff", "clock", "cloud", "clover", "coach", "cobra", "cocoa", "comet", "coral",
+    "cotton", "couch", "crane", "crown", "cube", "daisy", "dance", "delta", "desert", "diesel",
+    "dock", "dragon", "drum", "eagle", "easel", "echo", "elbow", "engine", "fabric", "falcon",
+    "farm", "feast", "fence", "ferry", "fiber", "fiddle", "finch", "flame", "flask", "fleet",
+    "flute", "forest", "fossil", "frame", "frost", "fruit", "gadget", "galaxy", "garden", "garlic",
+    "gecko", "ghost", "giant", "ginger", "globe", "glove", "goose", "grain", "grape", "gravel",
+    "guitar", "hammer", "harbor", "hazel", "helmet", "hero", "hobby", "honey", "hotel", "igloo",
+    "island", "ivory", "jacket", "jaguar", "jazz", "jelly", "jewel", "jockey", "jungle", "kayak",
+    "kernel", "kettle", "kiosk", "kite", "kiwi", "koala", "label", "ladder", "lagoon", "lamp",
+    "laser", "lava", "lemon", "lens", "lilac", "linen", "lizard", "locket", "lotus", "lunar",
+    "magnet", "mango", "maple", "marble", "meadow", "melon", "mentor", "mirror", "mitten",
+    "monkey", "mosaic", "motor", "muffin", "museum", "napkin", "nectar", "needle", "nest",
+    "noodle", "nugget", "oasis", "ocean", "olive", "onion", "opera", "orbit", "orchid", "otter",
+    "oven", "oyster", "paddle", "palace", "panda", "paper", "parrot", "peach", "pearl", "pebble",
+    "pencil", "pepper", "piano", "pilot", "pirate", "pizza", "planet", "plum", "pocket", "polar",
+    "pony", "poppy", "potato", "prism", "puzzle", "quartz", "quilt", "rabbit", "radar", "radio",
+    "raven", "razor", "recipe", "reef", "ribbon", "river", "robot", "rocket", "rose", "ruby",
+    "saddle", "salad", "salmon", "sandal", "satin", "scarf", "shadow", "shell", "silver", "skate",
+    "sketch", "sled", "snail", "sonic", "spider", "spoon", "squid", "stamp", "statue", "stone",
+    "storm", "sugar", "summit", "sunset", "swan", "table", "tango", "temple", "tennis", "tiger",
+    "timber", "toast", "tomato", "torch", "tower", "tulip", "tunnel", "turtle", "tuxedo", "velvet",
+    "violin", "wagon", "walnut", "walrus", "wizard", "yacht", "yogurt", "zebra",
+];
+
+pub fn encode(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| WORDS[usize::from(b)])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Reverses [`encode`]. Words are matched case-insensitively, and by their
+/// first four letters if that many were typed.
+pub fn decode(text: &str) -> Result<Vec<u8>, String> {
+    text.split_whitespace()
+        .map(|word| {
+            let word = word.to_ascii_lowercase();
+            WORDS
+                .iter()
+                .position(|w| *w == word || (word.len() >= 4 && w.starts_with(&word)))
+                .map(|i| i as u8)
+                .ok_or_else(|| format!("unknown word {word:?}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes_are_unique() {
+        let mut prefixes = WORDS
+            .iter()
+            .map(|w| &w[..w.len().min(4)])
+            .collect::<Vec<_>>();
+        prefixes.sort_unstable();
+        prefixes.dedup();
+        assert_eq!(prefixes.len(), WORDS.len());
+    }
+
+    #[test]
+    fn round_trips_every_byte() {
+        let bytes = (0..=255).collect::<Vec<u8>>();
+        assert_eq!(decode(&encode(&bytes)).unwrap(), bytes);
+        assert_eq!(decode("ACORN zebr").unwrap(), [0, 255]);
+        assert!(decode("acorn quux").is_err());
+    }
+}
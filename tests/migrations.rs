@@ -0,0 +1,108 @@
+//! Vault files written by earlier releases, in `fixtures/`, each holding the
+//! logins mail/me/hunter2 and bank/you/s3cret under the password
+//! "correct horse".
+
+use lockbox::{
+    Credential, Error, LoadError, LockMode, VaultHandle,
+    format::{self, FORMAT_VERSION, VaultDocument},
+};
+use serde_json::Value;
+use std::{fs, path::Path, time::Duration};
+
+const VERSIONS: [&str; 3] = ["v0", "v1", "v2"];
+
+fn password() -> Credential {
+    Credential::Password("correct horse".to_owned().into())
+}
+
+fn fixture(version: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(format!("tests/fixtures/{version}.json"));
+    fs::read(path).unwrap()
+}
+
+fn unlocked(path: &Path, mode: LockMode) -> VaultHandle {
+    let mut vault = VaultHandle::open(path, mode, Duration::ZERO).unwrap();
+    vault.unlock(&password()).unwrap();
+    vault
+}
+
+fn assert_logins(vault: &VaultHandle) {
+    let logins = vault
+        .entries()
+        .unwrap()
+        .iter()
+        .map(|e| {
+            let password = e.password().map(|p| p.to_string()).unwrap_or_default();
+            (
+                e.id,
+                e.service.clone(),
+                e.username().unwrap_or("").to_owned(),
+                password,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        logins,
+        [
+            (1, "mail".into(), "me".into(), "hunter2".into()),
+            (2, "bank".into(), "you".into(), "s3cret".into()),
+        ]
+    );
+}
+
+fn format_version(path: &Path) -> u64 {
+    let doc: Value = serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+    doc["header"]["format_version"].as_u64().unwrap()
+}
+
+#[test]
+fn old_versions_parse_as_the_current_layout() {
+    for version in VERSIONS {
+        let Ok(VaultDocument::Encrypted(enc)) = format::parse(&fixture(version)) else {
+            panic!("{version} did not parse as an encrypted vault");
+        };
+        assert_eq!(enc.header.format_version, FORMAT_VERSION, "{version}");
+        assert_eq!(enc.header.key_slots.len(), 1, "{version}");
+        // Only files from version 2 on sealed their header.
+        assert_eq!(enc.associated_data.is_some(), version == "v2");
+    }
+}
+
+#[test]
+fn old_versions_open_and_are_saved_as_the_current_one() {
+    for version in VERSIONS {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.json");
+        fs::write(&path, fixture(version)).unwrap();
+
+        assert_logins(&unlocked(&path, LockMode::Shared));
+        unlocked(&path, LockMode::Exclusive).save().unwrap();
+
+        assert_eq!(
+            format_version(&path),
+            u64::from(FORMAT_VERSION),
+            "{version}"
+        );
+        assert_logins(&unlocked(&path, LockMode::Shared));
+    }
+}
+
+#[test]
+fn newer_versions_are_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    fs::write(&path, fixture("v2")).unwrap();
+    unlocked(&path, LockMode::Exclusive).save().unwrap();
+
+    let mut doc: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    doc["header"]["format_version"] = (FORMAT_VERSION + 1).into();
+    fs::write(&path, serde_json::to_vec(&doc).unwrap()).unwrap();
+
+    let mut vault = VaultHandle::open(&path, LockMode::Shared, Duration::ZERO).unwrap();
+    let err = vault.unlock(&password()).unwrap_err();
+    assert!(
+        matches!(err, Error::Load(LoadError::UnsupportedFormat(_))),
+        "{err}"
+    );
+    assert_eq!(err.exit_code(), 78);
+}
@@ -0,0 +1,364 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use lockbox::{
+    Credential, EntryData, Error, KdfParams, LoadError, LockMode, VaultHandle, history::PastData,
+};
+use serde_json::Value;
+use std::{fs, path::Path, time::Duration};
+
+const FAST: KdfParams = KdfParams {
+    iterations: 3,
+    memory_kib: 64,
+};
+
+fn password(s: &str) -> Credential {
+    Credential::Password(s.to_owned().into())
+}
+
+fn create(path: &Path) -> VaultHandle {
+    VaultHandle::create(path, &password("correct horse"), FAST, Duration::ZERO).unwrap()
+}
+
+#[test]
+fn entries_survive_save_and_reopen() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    let mut vault = create(&path);
+    let mail = vault
+        .add_entry("mail".into(), "me".into(), "hunter2".to_owned().into())
+        .unwrap();
+    let bank = vault
+        .add_entry("bank".into(), "me".into(), "s3cret".to_owned().into())
+        .unwrap();
+    vault.entry_mut(mail).unwrap().data = EntryData::Login {
+        username: "me@example.com".into(),
+        password: "hunter2".to_owned().into(),
+    };
+    vault.remove_entry(bank).unwrap();
+    vault.save().unwrap();
+    drop(vault);
+
+    let mut vault = VaultHandle::open(&path, LockMode::Shared, Duration::ZERO).unwrap();
+    assert!(!vault.is_unlocked());
+    vault.unlock(&password("correct horse")).unwrap();
+
+    let entries = vault.entries().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].username(), Some("me@example.com"));
+    assert_eq!(
+        vault.entry(mail).unwrap().password().map(|p| &**p),
+        Some("hunter2")
+    );
+    assert!(matches!(vault.entry(bank), Err(Error::NoSuchEntry(_))));
+}
+
+#[test]
+fn wrong_password_is_a_typed_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    drop(create(&path));
+
+    let mut vault = VaultHandle::open(&path, LockMode::Shared, Duration::ZERO).unwrap();
+    let err = vault.unlock(&password("wrong horse")).unwrap_err();
+
+    assert!(
+        matches!(err, Error::Load(LoadError::WrongPassword)),
+        "{err}"
+    );
+    assert_eq!(err.exit_code(), 77);
+    assert!(!vault.is_unlocked());
+}
+
+#[test]
+fn handles_exclude_each_other_and_never_overwrite() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    let writer = create(&path);
+
+    let err = VaultHandle::open(&path, LockMode::Shared, Duration::ZERO).unwrap_err();
+    assert!(matches!(err, Error::Lock(_)), "{err}");
+    drop(writer);
+
+    let err = VaultHandle::create(&path, &password("other"), FAST, Duration::ZERO).unwrap_err();
+    assert!(matches!(err, Error::AlreadyExists(_)), "{err}");
+
+    let missing = dir.path().join("missing.json");
+    let err = VaultHandle::open(&missing, LockMode::Shared, Duration::ZERO).unwrap_err();
+    assert!(matches!(err, Error::Load(LoadError::NotFound(_))), "{err}");
+}
+
+#[test]
+fn changed_values_can_be_restored_from_history() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    let mut vault = create(&path);
+    let login = |password: &str| EntryData::Login {
+        username: "me".into(),
+        password: password.to_owned().into(),
+    };
+    let id = vault
+        .add_entry("mail".into(), "me".into(), "first".to_owned().into())
+        .unwrap();
+    vault.update_entry(id, login("second")).unwrap();
+    vault.update_entry(id, login("third")).unwrap();
+    assert_eq!(vault.entry(id).unwrap().history.len(), 2);
+
+    vault.restore_entry_version(id, 2).unwrap();
+    let entry = vault.entry(id).unwrap();
+    assert_eq!(entry.password().map(|p| &**p), Some("first"));
+    assert_eq!(entry.history[0].data, login("third"));
+    assert_eq!(entry.history[1].data, login("second"));
+    assert!(matches!(
+        vault.restore_entry_version(id, 3),
+        Err(Error::NoSuchVersion { number: 3, .. })
+    ));
+
+    vault.store_mut().unwrap().settings.history.keep_last = 1;
+    vault.update_entry(id, login("fourth")).unwrap();
+    assert_eq!(
+        vault.entry(id).unwrap().history,
+        [PastData {
+            replaced_ms: vault.entry(id).unwrap().history[0].replaced_ms,
+            data: login("first"),
+        }]
+    );
+    assert!(
+        vault
+            .update_entry(
+                id,
+                EntryData::SecureNote {
+                    text: String::new().into()
+                }
+            )
+            .is_err()
+    );
+}
+
+#[test]
+fn entries_are_found_by_id_or_unambiguous_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut vault = create(&dir.path().join("db.json"));
+    for (service, username) in [("Mail", "me"), ("bank", "me"), ("bank", "you"), ("7", "me")] {
+        vault
+            .add_entry(service.into(), username.into(), "pw".to_owned().into())
+            .unwrap();
+    }
+
+    assert_eq!(vault.find_entry("2").unwrap().service, "bank");
+    assert_eq!(vault.find_entry("mail").unwrap().id, 1);
+    assert_eq!(vault.find_entry("7").unwrap().id, 4);
+    assert!(matches!(
+        vault.find_entry("bank"),
+        Err(Error::Ambiguous { ref ids, .. }) if ids == &[2, 3]
+    ));
+    assert!(matches!(vault.find_entry("shop"), Err(Error::NoMatch(_))));
+}
+
+#[test]
+fn edited_entries_keep_their_id_across_saves() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    let mut vault = create(&path);
+    let id = vault
+        .add_entry("mail".into(), "me".into(), "hunter2".to_owned().into())
+        .unwrap();
+    let created = vault.entry(id).unwrap().created_ms;
+
+    let mut edited = vault.entry(id).unwrap().clone();
+    edited.service = "webmail".into();
+    edited.data = EntryData::Login {
+        username: "me".into(),
+        password: "hunter3".to_owned().into(),
+    };
+    assert!(vault.edit_entry(id, edited.clone()).unwrap());
+    assert!(!vault.edit_entry(id, edited.clone()).unwrap());
+    edited.service.clear();
+    assert!(matches!(
+        vault.edit_entry(id, edited),
+        Err(Error::Invalid(_))
+    ));
+    vault.save().unwrap();
+    drop(vault);
+
+    let mut vault = VaultHandle::open(&path, LockMode::Shared, Duration::ZERO).unwrap();
+    vault.unlock(&password("correct horse")).unwrap();
+    let entry = vault.find_entry("webmail").unwrap();
+    assert_eq!((entry.id, entry.created_ms), (id, created));
+    assert_eq!(entry.password().map(|p| &**p), Some("hunter3"));
+    assert_eq!(entry.history.len(), 1);
+}
+
+/// Flips the low bit of the first byte of a base64 value.
+fn flip_b64(value: &mut Value) {
+    let mut bytes = STANDARD.decode(value.as_str().unwrap()).unwrap();
+    bytes[0] ^= 1;
+    *value = STANDARD.encode(bytes).into();
+}
+
+#[test]
+fn modified_files_fail_their_integrity_check() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    let mut vault = create(&path);
+    vault
+        .add_entry("mail".into(), "me".into(), "hunter2".to_owned().into())
+        .unwrap();
+    vault.save().unwrap();
+    drop(vault);
+    let original: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+
+    let load = |tamper: &dyn Fn(&mut Value)| {
+        let mut doc = original.clone();
+        tamper(&mut doc);
+        fs::write(&path, serde_json::to_vec(&doc).unwrap()).unwrap();
+        let mut vault = VaultHandle::open(&path, LockMode::Shared, Duration::ZERO).unwrap();
+        vault.unlock(&password("correct horse"))
+    };
+    let integrity = |what: &str, tamper: &dyn Fn(&mut Value)| match load(tamper) {
+        Err(Error::Load(LoadError::Integrity(_))) => {}
+        other => panic!("{what}: expected an integrity error, got {other:?}"),
+    };
+
+    assert!(load(&|_| {}).is_ok());
+    integrity("ciphertext", &|doc| flip_b64(&mut doc["blob_b64"]));
+    integrity("vault id", &|doc| {
+        doc["header"]["vault_id"] = "0".repeat(32).into();
+    });
+    integrity("added header field", &|doc| {
+        doc["header"]["note"] = "x".into();
+    });
+    integrity("wrapped key", &|doc| {
+        flip_b64(&mut doc["header"]["key_slots"][0]["wrapped_key_b64"]);
+    });
+    integrity("slot label", &|doc| {
+        doc["header"]["key_slots"][0]["label"] = "other".into();
+    });
+    integrity("slot creation time", &|doc| {
+        doc["header"]["key_slots"][0]["created_ms"] = 1.into();
+    });
+    // A changed salt derives a different key, which no check can tell apart
+    // from a wrong password.
+    assert!(matches!(
+        load(&|doc| flip_b64(&mut doc["header"]["key_slots"][0]["salt_b64"])),
+        Err(Error::Load(LoadError::WrongPassword))
+    ));
+}
+
+#[test]
+fn changing_the_password_revokes_the_other_slots() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    let mut vault = create(&path);
+    let first = vault.keyring().unwrap().slots()[0].id.clone();
+    let spare = vault
+        .add_slot(&password("spare password"), FAST, "spare")
+        .unwrap();
+    vault.save().unwrap();
+    drop(vault);
+
+    let mut vault = VaultHandle::open(&path, LockMode::Exclusive, Duration::ZERO).unwrap();
+    vault.unlock(&password("correct horse")).unwrap();
+    let revoked = vault
+        .change_credential(&password("battery staple"))
+        .unwrap();
+    assert_eq!(
+        revoked.iter().map(|s| &s.id).collect::<Vec<_>>(),
+        [&spare.id]
+    );
+    assert_eq!(vault.unlocked_kdf_params().unwrap(), FAST);
+    vault.save().unwrap();
+    drop(vault);
+
+    let mut vault = VaultHandle::open(&path, LockMode::Shared, Duration::ZERO).unwrap();
+    for old in ["correct horse", "spare password"] {
+        assert!(vault.unlock(&password(old)).is_err());
+    }
+    vault.unlock(&password("battery staple")).unwrap();
+    let slots = vault.keyring().unwrap().slots();
+    assert_eq!(slots.len(), 1);
+    assert_eq!(slots[0].id, first);
+}
+
+#[test]
+fn recovery_shares_reset_the_credentials() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    let mut vault = create(&path);
+    let shares = vault.add_recovery_shares(2, 3, FAST).unwrap();
+    let slot_id = shares[0].slot_id.clone();
+    vault.save().unwrap();
+    drop(vault);
+
+    let mut vault = VaultHandle::open(&path, LockMode::Exclusive, Duration::ZERO).unwrap();
+    let opened = vault
+        .unlock_with_shares(shares.into_iter().skip(1).collect())
+        .unwrap();
+    assert_eq!(opened, slot_id);
+    let revoked = vault
+        .reset_credentials(&password("battery staple"), FAST, "master password")
+        .unwrap();
+    assert_eq!(revoked.len(), 2);
+    vault.save().unwrap();
+    drop(vault);
+
+    let mut vault = VaultHandle::open(&path, LockMode::Shared, Duration::ZERO).unwrap();
+    assert!(vault.unlock(&password("correct horse")).is_err());
+    vault.unlock(&password("battery staple")).unwrap();
+}
+
+#[test]
+fn saving_reports_the_leftovers_of_interrupted_saves() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    let vault = create(&path);
+    let leftover = dir.path().join("db.json.tmp");
+    fs::write(&leftover, b"partial").unwrap();
+    assert_eq!(vault.save().unwrap(), std::slice::from_ref(&leftover));
+    assert!(!leftover.exists());
+    assert!(vault.save().unwrap().is_empty());
+}
+
+#[test]
+fn recorded_uses_take_no_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    let mut vault = create(&path);
+    let id = vault
+        .add_entry("mail".into(), "me".into(), "hunter2".to_owned().into())
+        .unwrap();
+    vault.save().unwrap();
+    let backups = lockbox::backup::list(&path).unwrap().len();
+
+    for _ in 0..3 {
+        vault.mark_used(id).unwrap();
+        vault.save_without_backup().unwrap();
+    }
+    assert_eq!(lockbox::backup::list(&path).unwrap().len(), backups);
+    drop(vault);
+
+    let mut vault = VaultHandle::open(&path, LockMode::Shared, Duration::ZERO).unwrap();
+    vault.unlock(&password("correct horse")).unwrap();
+    let entry = vault.entry(id).unwrap();
+    assert!(entry.last_used_ms.is_some());
+    assert!(entry.last_used_ms >= entry.modified_ms);
+}
+
+#[test]
+fn rewrapping_the_password_keeps_the_other_slots() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    let mut vault = create(&path);
+    vault
+        .add_slot(&password("spare password"), FAST, "spare")
+        .unwrap();
+    vault
+        .rewrap_credential(&password("battery staple"))
+        .unwrap();
+    vault.save().unwrap();
+    drop(vault);
+
+    let mut vault = VaultHandle::open(&path, LockMode::Shared, Duration::ZERO).unwrap();
+    assert!(vault.unlock(&password("correct horse")).is_err());
+    vault.unlock(&password("spare password")).unwrap();
+    vault.unlock(&password("battery staple")).unwrap();
+    assert_eq!(vault.keyring().unwrap().slots().len(), 2);
+}